@@ -65,7 +65,7 @@ fn depth_first(c: &mut Criterion) {
 
     for i in INPUTS {
         g.bench_with_input(i.0, &(i.1, i.2), |b, args| {
-            b.iter(|| do_matching(args.0, args.1, true))
+            b.iter(|| do_matching(args.0, args.1, 0, true))
         });
     }
 }
@@ -76,7 +76,7 @@ fn width_first(c: &mut Criterion) {
 
     for i in INPUTS {
         g.bench_with_input(i.0, &(i.1, i.2), |b, args| {
-            b.iter(|| do_matching(args.0, args.1, false))
+            b.iter(|| do_matching(args.0, args.1, 0, false))
         });
     }
 }
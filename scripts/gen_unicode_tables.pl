@@ -0,0 +1,174 @@
+#!/usr/bin/env perl
+# Unicode の表を Rust のソースコードとして生成するスクリプト
+#
+# Perl に同梱されている Unicode データベース（Unicode::UCD）を使うため、
+# ネットワークへのアクセスは不要。
+#
+# 使い方:
+#   perl scripts/gen_unicode_tables.pl case_folding > src/engine/unicode_tables/case_folding.rs
+#   perl scripts/gen_unicode_tables.pl perl > src/engine/unicode_tables/perl.rs
+#   perl scripts/gen_unicode_tables.pl property > src/engine/unicode_tables/property.rs
+use strict;
+use warnings;
+use Unicode::UCD qw(all_casefolds prop_invlist prop_values prop_value_aliases);
+
+my %generators = (
+    case_folding => \&case_folding,
+    perl         => \&perl,
+    property     => \&property,
+);
+
+my $table = shift @ARGV // '';
+my $generator = $generators{$table}
+    or die "usage: $0 <" . join('|', sort keys %generators) . ">\n";
+
+print "// このファイルは scripts/gen_unicode_tables.pl により生成したもの。直接編集しないこと。\n";
+print "// Unicode " . Unicode::UCD::UnicodeVersion() . "\n";
+print "\n";
+$generator->();
+
+# 文字を Rust の文字リテラルに変換する
+sub char_literal {
+    my ($cp) = @_;
+    if ($cp >= 0x20 && $cp < 0x7F) {
+        my $c = chr($cp);
+        return "'\\''" if $c eq "'";
+        return "'\\\\'" if $c eq "\\";
+        return "'$c'";
+    }
+    return sprintf("'\\u{%X}'", $cp);
+}
+
+# 性質 $property を満たす文字の範囲の列を返す（サロゲート領域は除く）
+sub ranges {
+    my ($property) = @_;
+    my $re = qr/\A\p{$property}\z/;
+    my @ranges;
+    for my $cp (0 .. 0x10FFFF) {
+        next if $cp >= 0xD800 && $cp <= 0xDFFF;
+        next unless chr($cp) =~ $re;
+        if (@ranges && $ranges[-1][1] == $cp - 1) {
+            $ranges[-1][1] = $cp;
+        } else {
+            push @ranges, [$cp, $cp];
+        }
+    }
+    return @ranges;
+}
+
+# 範囲の列の表を出力する
+sub print_ranges {
+    my ($name, $doc, @ranges) = @_;
+    print "/// $doc\n";
+    print "pub const $name: &[(char, char)] = &[\n";
+    for my $r (@ranges) {
+        print "    (" . char_literal($r->[0]) . ", " . char_literal($r->[1]) . "),\n";
+    }
+    print "];\n";
+}
+
+# \d, \s, \w の Unicode 版の表（Unicode Technical Standard #18 の定義）
+sub perl {
+    print_ranges('PERL_DIGIT', '\\d: 10進数字（General_Category が Decimal_Number）', ranges('Nd'));
+    print "\n";
+    print_ranges('PERL_SPACE', '\\s: 空白文字（White_Space）', ranges('White_Space'));
+    print "\n";
+    print_ranges(
+        'PERL_WORD',
+        '\\w: 単語を構成する文字（Alphabetic, Mark, Decimal_Number, Connector_Punctuation, Join_Control）',
+        ranges('Word'),
+    );
+}
+
+# 性質の値を満たす文字の範囲の列を、反転リストから求める（サロゲート領域は除く）
+# e.g. invlist_ranges('gc=Lu')
+sub invlist_ranges {
+    my ($property) = @_;
+    my @invlist = prop_invlist($property);
+    push @invlist, 0x110000 if @invlist % 2;
+    my @ranges;
+    while (my ($start, $end) = splice(@invlist, 0, 2)) {
+        $end -= 1;
+        if ($start < 0xD800 && $end > 0xDFFF) {
+            push @ranges, [$start, 0xD7FF], [0xE000, $end];
+        } elsif ($start < 0xD800) {
+            push @ranges, [$start, $end < 0xD800 ? $end : 0xD7FF];
+        } elsif ($end > 0xDFFF) {
+            push @ranges, [$start > 0xDFFF ? $start : 0xE000, $end];
+        }
+    }
+    return @ranges;
+}
+
+# 性質の名前を正規化する（大文字・小文字、空白、_ と - を区別しない）
+sub normalize {
+    my ($name) = @_;
+    $name = lc($name);
+    $name =~ s/[ _-]//g;
+    return $name;
+}
+
+# 性質 $prop の値ごとの範囲の表と、別名も含めた正規化した名前から表を引くための表を出力する
+sub print_property {
+    my ($name, $prop, $prefix, $doc) = @_;
+    my %lookup;
+    for my $value (sort(prop_values($prop))) {
+        # 別名を持たない値（文字の割り当てがない Hrkt など）は除く
+        my @aliases = prop_value_aliases($prop, $value) or next;
+        my $const = $prefix . '_' . uc($aliases[1] // $aliases[0]);
+        print "const $const: &[(char, char)] = &[\n";
+        for my $r (invlist_ranges("$prop=$value")) {
+            print "    (" . char_literal($r->[0]) . ", " . char_literal($r->[1]) . "),\n";
+        }
+        print "];\n\n";
+        $lookup{normalize($_)} = $const for @aliases;
+    }
+
+    print "/// $doc\n";
+    print "///\n";
+    print "/// (正規化した名前, 文字の範囲) を名前の昇順に並べたもの。別名も含む。\n";
+    print "pub const $name: &[(&str, &[(char, char)])] = &[\n";
+    for my $alias (sort keys %lookup) {
+        print "    (\"$alias\", $lookup{$alias}),\n";
+    }
+    print "];\n";
+}
+
+# \p{..} で指定する General_Category と Script の表
+sub property {
+    print_property('GENERAL_CATEGORY', 'gc', 'GC', 'General_Category の値ごとの文字の範囲');
+    print "\n";
+    print_property('SCRIPT', 'sc', 'SC', 'Script の値ごとの文字の範囲');
+}
+
+# 単純大文字小文字変換（CaseFolding.txt のステータス C と S）で同一視される文字の表
+sub case_folding {
+    # 変換後の文字ごとに、同一視される文字をまとめる
+    my %orbits;
+    my $folds = all_casefolds();
+    for my $cp (keys %$folds) {
+        my $fold = $folds->{$cp};
+        next unless $fold->{status} eq 'C' || $fold->{status} eq 'S';
+        my $to = hex($fold->{simple});
+        $orbits{$to}{$to} = 1;
+        $orbits{$to}{$cp} = 1;
+    }
+
+    my %equivalents;
+    for my $orbit (values %orbits) {
+        my @members = sort { $a <=> $b } keys %$orbit;
+        for my $cp (@members) {
+            $equivalents{$cp} = [grep { $_ != $cp } @members];
+        }
+    }
+
+    print "/// 単純大文字小文字変換で同一視される文字の表\n";
+    print "///\n";
+    print "/// (文字, 同一視される他の文字) を文字の昇順に並べたもの。\n";
+    print "pub const CASE_FOLDING_SIMPLE: &[(char, &[char])] = &[\n";
+    for my $cp (sort { $a <=> $b } keys %equivalents) {
+        my $others = join(', ', map { char_literal($_) } @{$equivalents{$cp}});
+        print "    (" . char_literal($cp) . ", &[$others]),\n";
+    }
+    print "];\n";
+}
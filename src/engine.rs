@@ -1,9 +1,19 @@
 use std::fmt::{Display, Formatter};
 use crate::helpers::DynError;
+use self::class::CharClass;
 
+mod captures;
+mod class;
 mod parser;
 mod codegen;
 mod evaluator;
+mod regex;
+mod replacer;
+mod unicode_tables;
+
+pub use self::captures::Captures;
+pub use self::regex::{CaptureMatches, Match, Matches, Regex, RegexBuilder, Split, SplitN};
+pub use self::replacer::Replacer;
 
 #[derive(Debug)]
 pub enum Instruction {
@@ -14,6 +24,17 @@ pub enum Instruction {
     Split(usize, usize), // L1のアドレス、L2のアドレス
     Caret,
     Dollar,
+    Class(CharClass), // 文字クラスに含まれる 1 文字
+    Save(usize), // 現在の位置をキャプチャのスロットに記録
+    LineStart(bool), // 文字列か行の先頭（bool は \r\n を行の区切りとするかどうか）
+    LineEnd(bool),   // 文字列か行の末尾
+    WordBoundary(bool),    // \b 単語の境界（bool は Unicode の単語の定義を使うかどうか）
+    NotWordBoundary(bool), // \B 単語の境界以外
+    LookAhead(bool, usize), // 先読み（否定かどうか、先読みの後のアドレス）。直後から sub_end までが先読みの式
+    LookBehind(bool, usize, usize, usize), // 後読み（否定かどうか、最小の長さ、最大の長さ、後読みの後のアドレス）
+    Atomic(usize), // アトミックグループ（グループの後のアドレス）。直後から sub_end までがグループの式
+    SubEnd, // 先読み・後読み・アトミックグループの式の終わり
+    BackRef(usize, bool), // 後方参照（グループの番号、大文字・小文字を同一視するかどうか）
 }
 
 impl Display for Instruction {
@@ -26,6 +47,29 @@ impl Display for Instruction {
             Instruction::Dot => write!(f, "dot"),
             Instruction::Caret => write!(f, "caret"),
             Instruction::Dollar => write!(f, "dollar"),
+            Instruction::Class(class) => write!(f, "class {}", class),
+            Instruction::Save(n) => write!(f, "save {}", n),
+            Instruction::LineStart(false) => write!(f, "line_start"),
+            Instruction::LineStart(true) => write!(f, "line_start_crlf"),
+            Instruction::LineEnd(false) => write!(f, "line_end"),
+            Instruction::LineEnd(true) => write!(f, "line_end_crlf"),
+            Instruction::WordBoundary(true) => write!(f, "word_boundary"),
+            Instruction::WordBoundary(false) => write!(f, "word_boundary_ascii"),
+            Instruction::NotWordBoundary(true) => write!(f, "not_word_boundary"),
+            Instruction::NotWordBoundary(false) => write!(f, "not_word_boundary_ascii"),
+            Instruction::LookAhead(negated, addr) => {
+                write!(f, "look_ahead {}, {:>04}", if *negated { "neg" } else { "pos" }, addr)
+            }
+            Instruction::LookBehind(negated, min, max, addr) => write!(
+                f,
+                "look_behind {}, {min}, {max}, {:>04}",
+                if *negated { "neg" } else { "pos" },
+                addr
+            ),
+            Instruction::Atomic(addr) => write!(f, "atomic {:>04}", addr),
+            Instruction::SubEnd => write!(f, "sub_end"),
+            Instruction::BackRef(n, false) => write!(f, "backref {}", n),
+            Instruction::BackRef(n, true) => write!(f, "backref_ci {}", n),
         }
     }
 }
@@ -34,23 +78,24 @@ impl Display for Instruction {
 /// # 利用例
 /// ```
 /// use regex;
-/// regex::do_matching("abc|(de|cd)+", "decddede", true);
+/// regex::do_matching("abc|(de|cd)+", "decddede", 0, true);
 /// ```
 ///
 /// # 引数
 /// expr → 正規表現
 /// line → マッチ対象の文字列
-/// is_depth → 深さ優先探索かどうか
+/// index → line が元の文字列の何文字目から始まるか
+/// is_depth → 深さ優先探索かどうか（false の場合は幅優先探索）
 ///
 /// # 返り値
 /// エラーなく実行してマッチング成功したら true
 /// エラーなく実行してマッチング失敗したら false
 /// エラーがある場合は Err
 pub fn do_matching(expr: &str, line: &str, index: usize, is_depth: bool) -> Result<bool,DynError>{
-    let ast = parser::parse(expr)?; // AST変換
-    let code = codegen::get_code(&ast)?; // 命令に変換
+    let ast = parser::parse(expr, parser::Flags::default())?; // AST変換
+    let code = codegen::get_code(&ast, codegen::DEFAULT_SIZE_LIMIT)?; // 命令に変換
     let line = line.chars().collect::<Vec<char>>();
-    Ok(evaluator::eval(&code, &&line, index, is_depth)?) // 正規表現評価
+    Ok(evaluator::eval(&code, &line, index, is_depth)?) // 正規表現評価
 }
 
 /// 正規表現をパースしてコード生成し、
@@ -68,12 +113,12 @@ pub fn do_matching(expr: &str, line: &str, index: usize, is_depth: bool) -> Resu
 /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
 pub fn print(expr: &str) -> Result<(), DynError> {
     println!("expr: {expr}");
-    let ast = parser::parse(expr)?;
+    let ast = parser::parse(expr, parser::Flags::default())?;
     println!("AST: {:?}", ast);
 
     println!();
     println!("code:");
-    let code = codegen::get_code(&ast)?;
+    let code = codegen::get_code(&ast, codegen::DEFAULT_SIZE_LIMIT)?;
     for (n, c) in code.iter().enumerate() {
         println!("{:>04}: {c}", n);
     }
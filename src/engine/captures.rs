@@ -0,0 +1,157 @@
+//! キャプチャグループの位置
+use std::collections::HashMap;
+use std::ops::Index;
+use std::sync::Arc;
+use crate::engine::regex::Match;
+
+/// マッチした箇所の、各キャプチャグループの位置
+///
+/// 0 番目のグループはマッチ全体を表す。
+/// 位置は元の文字列のバイト単位で表す。
+#[derive(Debug, Clone)]
+pub struct Captures<'t> {
+    line: &'t str,
+    slots: Vec<Option<usize>>, // 2n, 2n+1 番目が n 番目のグループの開始位置と終了位置
+    names: Arc<HashMap<String, usize>>, // 名前付きグループの名前と番号
+}
+
+impl<'t> Captures<'t> {
+    pub(crate) fn new(
+        line: &'t str,
+        slots: Vec<Option<usize>>,
+        names: Arc<HashMap<String, usize>>,
+    ) -> Captures<'t> {
+        Captures { line, slots, names }
+    }
+
+    /// i 番目のグループにマッチした箇所を返す。
+    /// グループが存在しないか、マッチに参加しなかった場合は None。
+    pub fn get(&self, i: usize) -> Option<Match<'t>> {
+        match (self.slots.get(2 * i), self.slots.get(2 * i + 1)) {
+            (Some(Some(start)), Some(Some(end))) => Some(Match::new(self.line, *start, *end)),
+            _ => None,
+        }
+    }
+
+    /// name という名前のグループにマッチした箇所を返す。
+    /// グループが存在しないか、マッチに参加しなかった場合は None。
+    pub fn name(&self, name: &str) -> Option<Match<'t>> {
+        self.names.get(name).and_then(|&i| self.get(i))
+    }
+
+    /// グループの数を返す。マッチ全体を表す 0 番目のグループも数える。
+    #[allow(clippy::len_without_is_empty)] // 0 番目のグループが必ずあるため空にならない
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    /// 各グループにマッチした箇所を、グループの番号順に返すイテレータ
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match<'t>>> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /// テンプレート中のグループの参照を、グループにマッチした部分文字列に置き換えて dst の末尾に追加する。
+    ///
+    /// - `$n`, `${n}`: n 番目のグループ
+    /// - `$name`, `${name}`: name という名前のグループ
+    /// - `$$`: `$` そのもの
+    ///
+    /// `$name` の name は英数字と `_` の最長の並びとみなすため、
+    /// 直後に英数字が続く場合は `${1}a` のように波カッコで囲む。
+    /// 存在しないグループや、マッチに参加しなかったグループは空文字列に置き換える。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// let re = Regex::new("([a-z]+)=([0-9]+)").unwrap();
+    /// let caps = re.captures("key=42").unwrap();
+    /// let mut dst = String::new();
+    /// caps.expand("$2=${1}_$$", &mut dst);
+    /// assert_eq!(dst, "42=key_$");
+    /// ```
+    pub fn expand(&self, template: &str, dst: &mut String) {
+        let mut rest = template;
+        while let Some(i) = rest.find('$') {
+            dst.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+
+            // $$ は $ そのもの
+            if let Some(r) = rest.strip_prefix('$') {
+                dst.push('$');
+                rest = r;
+                continue;
+            }
+
+            if let Some((name, len)) = parse_group_ref(rest) {
+                if let Some(m) = self.get_by_ref(name) {
+                    dst.push_str(m.as_str());
+                }
+                rest = &rest[len..];
+            } else {
+                // グループの参照として解釈できない $ はそのまま出力
+                dst.push('$');
+            }
+        }
+        dst.push_str(rest);
+    }
+
+    // テンプレート中のグループの参照から、グループにマッチした箇所を返す
+    fn get_by_ref(&self, name: &str) -> Option<Match<'t>> {
+        if let Ok(i) = name.parse::<usize>() {
+            self.get(i)
+        } else {
+            self.name(name)
+        }
+    }
+}
+
+// $ の直後からグループの参照を読み、(参照名, 読んだバイト数) を返す
+// e.g. "{1}abc" => ("1", 3), "name abc" => ("name", 4)
+fn parse_group_ref(rest: &str) -> Option<(&str, usize)> {
+    if let Some(r) = rest.strip_prefix('{') {
+        let end = r.find('}')?;
+        if end == 0 {
+            return None; // ${} は参照として扱わない
+        }
+        Some((&r[..end], end + 2))
+    } else {
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        Some((&rest[..end], end))
+    }
+}
+
+impl<'t> Index<&str> for Captures<'t> {
+    type Output = str;
+
+    /// name という名前のグループにマッチした部分文字列を返す。
+    ///
+    /// # パニック
+    ///
+    /// グループが存在しないか、マッチに参加しなかった場合はパニックする。
+    fn index(&self, name: &str) -> &str {
+        self.name(name)
+            .map(|m| m.as_str())
+            .unwrap_or_else(|| panic!("no group named '{name}'"))
+    }
+}
+
+impl<'t> Index<usize> for Captures<'t> {
+    type Output = str;
+
+    /// i 番目のグループにマッチした部分文字列を返す。
+    ///
+    /// # パニック
+    ///
+    /// グループが存在しないか、マッチに参加しなかった場合はパニックする。
+    fn index(&self, i: usize) -> &str {
+        self.get(i)
+            .map(|m| m.as_str())
+            .unwrap_or_else(|| panic!("no group at index '{i}'"))
+    }
+}
@@ -0,0 +1,215 @@
+//! 文字クラス（[a-z0-9_] や [^"] など）の表現
+use std::fmt::{self, Display, Formatter};
+use crate::engine::unicode_tables::case_folding::CASE_FOLDING_SIMPLE;
+use crate::engine::unicode_tables::perl::{PERL_DIGIT, PERL_SPACE, PERL_WORD};
+use crate::engine::unicode_tables::property::{GENERAL_CATEGORY, SCRIPT};
+
+/// 文字クラス
+///
+/// 文字の範囲 (始点, 終点) の列として表現する。
+/// 範囲は常に昇順に並べ、重なったり隣接したりする範囲は 1 つにまとめておく。
+/// 否定 [^...] は補集合を計算して表現するため、否定フラグは持たない。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    /// 範囲の列から文字クラスを生成する。
+    /// 範囲の始点は終点以下でなければならない。
+    pub fn new(mut ranges: Vec<(char, char)>) -> CharClass {
+        ranges.sort_unstable();
+
+        // 重なったり隣接したりする範囲をまとめる
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            if let Some(last) = merged.last_mut() {
+                if next_char(last.1).is_none_or(|c| start <= c) {
+                    last.1 = last.1.max(end);
+                    continue;
+                }
+            }
+            merged.push((start, end));
+        }
+
+        CharClass { ranges: merged }
+    }
+
+    /// \d に対応する文字クラスを返す。
+    /// unicode が false の場合は ASCII の [0-9] のみ。
+    pub fn perl_digit(unicode: bool) -> CharClass {
+        if unicode {
+            CharClass::new(PERL_DIGIT.to_vec())
+        } else {
+            CharClass::new(vec![('0', '9')])
+        }
+    }
+
+    /// \s に対応する文字クラスを返す。
+    /// unicode が false の場合は ASCII の [\t\n\v\f\r ] のみ。
+    pub fn perl_space(unicode: bool) -> CharClass {
+        if unicode {
+            CharClass::new(PERL_SPACE.to_vec())
+        } else {
+            CharClass::new(vec![('\t', '\r'), (' ', ' ')])
+        }
+    }
+
+    /// \w に対応する文字クラスを返す。
+    /// unicode が false の場合は ASCII の [0-9A-Za-z_] のみ。
+    pub fn perl_word(unicode: bool) -> CharClass {
+        if unicode {
+            CharClass::new(PERL_WORD.to_vec())
+        } else {
+            CharClass::new(vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')])
+        }
+    }
+
+    /// \p{name} に対応する文字クラスを返す。該当する性質がない場合は None。
+    ///
+    /// name には General_Category e.g. L, Lu, Letter と Script e.g. Greek, Grek を指定でき、
+    /// gc=Lu や sc=Greek のように性質の名前を付けてもよい。
+    /// 名前の大文字・小文字、空白、_ と - は区別しない。
+    pub fn unicode_property(name: &str) -> Option<CharClass> {
+        let (property, value) = match name.split_once('=') {
+            Some((property, value)) => (Some(normalize_property(property)), value),
+            None => (None, name),
+        };
+        let tables = match property.as_deref() {
+            None => vec![GENERAL_CATEGORY, SCRIPT],
+            Some("gc" | "generalcategory") => vec![GENERAL_CATEGORY],
+            Some("sc" | "script") => vec![SCRIPT],
+            Some(_) => return None,
+        };
+
+        let value = normalize_property(value);
+        tables.into_iter().find_map(|table| {
+            table
+                .binary_search_by_key(&value.as_str(), |(n, _)| n)
+                .ok()
+                .map(|i| CharClass::new(table[i].1.to_vec()))
+        })
+    }
+
+    /// 和集合を返す。
+    pub fn union(&self, other: &CharClass) -> CharClass {
+        let mut ranges = self.ranges.clone();
+        ranges.extend_from_slice(&other.ranges);
+        CharClass::new(ranges)
+    }
+
+    /// 補集合を返す。
+    pub fn negate(&self) -> CharClass {
+        let mut ranges = Vec::new();
+        let mut start = Some('\0'); // 次の範囲の始点候補
+        for &(s, e) in &self.ranges {
+            if let Some(st) = start {
+                if st < s {
+                    // s の直前の文字までが補集合に含まれる
+                    ranges.push((st, prev_char(s).unwrap()));
+                }
+            }
+            start = next_char(e);
+        }
+        if let Some(st) = start {
+            ranges.push((st, char::MAX));
+        }
+        CharClass { ranges }
+    }
+
+    /// 大文字・小文字を同一視した文字クラスを返す。
+    ///
+    /// 各範囲に含まれる文字に、Unicode の単純大文字小文字変換で同一視される文字を加える。
+    /// e.g. [a-c] => [A-Ca-c], [k] => [Kk\u{212A}]（ケルビン記号）
+    pub fn case_fold(&self) -> CharClass {
+        let mut ranges = self.ranges.clone();
+        for &(s, e) in &self.ranges {
+            // 表は文字の昇順に並んでいるので、範囲に含まれる部分だけを調べる
+            let start = CASE_FOLDING_SIMPLE.partition_point(|(c, _)| *c < s);
+            for (_, others) in CASE_FOLDING_SIMPLE[start..].iter().take_while(|(c, _)| *c <= e) {
+                ranges.extend(others.iter().map(|&o| (o, o)));
+            }
+        }
+        CharClass::new(ranges)
+    }
+
+    /// 文字 c が文字クラスに含まれるかを返す。
+    pub fn contains(&self, c: char) -> bool {
+        ranges_contains(&self.ranges, c)
+    }
+}
+
+/// 大文字・小文字を同一視した場合に、a と b が同じ文字とみなされるかを返す。
+/// Unicode の単純大文字小文字変換を使う。
+pub fn is_case_equivalent(a: char, b: char) -> bool {
+    a == b
+        || CASE_FOLDING_SIMPLE
+            .binary_search_by_key(&a, |(c, _)| *c)
+            .is_ok_and(|i| CASE_FOLDING_SIMPLE[i].1.contains(&b))
+}
+
+/// 文字 c が \w に含まれるかを返す。\b と \B の判定に使う。
+/// unicode が false の場合は ASCII の [0-9A-Za-z_] のみ。
+pub fn is_word_char(c: char, unicode: bool) -> bool {
+    if unicode {
+        ranges_contains(PERL_WORD, c)
+    } else {
+        c.is_ascii_alphanumeric() || c == '_'
+    }
+}
+
+// 昇順に並んだ範囲の列に、文字 c が含まれるかを二分探索で調べる
+fn ranges_contains(ranges: &[(char, char)], c: char) -> bool {
+    ranges
+        .binary_search_by(|&(s, e)| {
+            if e < c {
+                std::cmp::Ordering::Less
+            } else if c < s {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+impl Display for CharClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for &(s, e) in &self.ranges {
+            if s == e {
+                write!(f, "{}", s.escape_debug())?;
+            } else {
+                write!(f, "{}-{}", s.escape_debug(), e.escape_debug())?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+// 性質の名前を正規化する（大文字・小文字、空白、_ と - を区別しない）
+// e.g. Uppercase_Letter => uppercaseletter
+fn normalize_property(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+// c の次の文字を返す（サロゲート領域は飛ばす）
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        char::MAX => None,
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+// c の前の文字を返す（サロゲート領域は飛ばす）
+fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        '\0' => None,
+        _ => char::from_u32(c as u32 - 1),
+    }
+}
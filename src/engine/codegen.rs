@@ -1,11 +1,20 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use crate::engine::class::CharClass;
 use crate::engine::Instruction;
-use crate::engine::parser::AST;
+use crate::engine::parser::{match_len, AST};
 use crate::helpers::safe_add;
 
-pub fn get_code(ast: &AST) -> Result<Vec<Instruction>, CodeGenError> {
-    let mut generator = Generator::default();
+/// 生成する命令数の上限のデフォルト値
+pub const DEFAULT_SIZE_LIMIT: usize = 100_000;
+
+/// AST から命令列を生成する。
+/// 命令数が size_limit を超える場合はエラー。
+pub fn get_code(ast: &AST, size_limit: usize) -> Result<Vec<Instruction>, CodeGenError> {
+    let mut generator = Generator {
+        size_limit,
+        ..Default::default()
+    };
     generator.gen_code(ast)?;
     Ok(generator.insts)
 }
@@ -17,11 +26,20 @@ pub enum CodeGenError {
     FailStar,
     FailOr,
     FailQuestion,
+    FailRepeat,
+    FailLookAround,
+    FailAtomic,
+    SizeLimit(usize), // 命令数が上限を超えた
 }
 
 impl Display for CodeGenError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "CodeGenError: {:?}", self)
+        match self {
+            CodeGenError::SizeLimit(limit) => {
+                write!(f, "CodeGenError: exceeds size limit: limit = {limit}")
+            }
+            _ => write!(f, "CodeGenError: {:?}", self),
+        }
     }
 }
 
@@ -32,14 +50,21 @@ impl Error for CodeGenError{}
 #[derive(Default, Debug)]
 struct Generator {
     pc: usize, // プログラムカウンタ
-    insts: Vec<Instruction>
+    insts: Vec<Instruction>,
+    size_limit: usize, // 命令数の上限
 }
 
 
 impl Generator {
     // プログラムカウンタをインクリメント
+    // 命令数が上限を超えた場合はエラー
     fn inc_pc(&mut self) -> Result<(), CodeGenError> {
-        safe_add(&mut self.pc, &1, || CodeGenError::PCOverFlow)
+        safe_add(&mut self.pc, &1, || CodeGenError::PCOverFlow)?;
+        if self.pc > self.size_limit {
+            Err(CodeGenError::SizeLimit(self.size_limit))
+        } else {
+            Ok(())
+        }
     }
 
     // ASTをパターン分けして、コード生成を行う
@@ -48,12 +73,25 @@ impl Generator {
             AST::Char(c) => self.gen_char(*c)?,
             AST::Dot => self.gen_dot()?,
             AST::Or(e1, e2) => self.gen_or(e1, e2)?,
-            AST::Plus(e) => self.gen_plus(e)?,
-            AST::Star(e) => self.gen_star(e)?,
-            AST::Question(e) => self.gen_question(e)?,
+            AST::Plus(e, greedy) => self.gen_plus(e, *greedy)?,
+            AST::Star(e, greedy) => self.gen_star(e, *greedy)?,
+            AST::Question(e, greedy) => self.gen_question(e, *greedy)?,
             AST::Seq(v) => self.gen_seq(v)?,
             AST::Caret => self.gen_caret()?,
             AST::Dollar => self.gen_dollar()?,
+            AST::Class(class) => self.gen_class(class)?,
+            AST::Repeat(e, min, max, greedy) => self.gen_repeat(e, *min, *max, *greedy)?,
+            AST::Capture(e, n, _) => self.gen_capture(e, *n)?,
+            AST::LineStart(crlf) => self.gen_assert(Instruction::LineStart(*crlf))?,
+            AST::LineEnd(crlf) => self.gen_assert(Instruction::LineEnd(*crlf))?,
+            AST::WordBoundary(unicode) => self.gen_assert(Instruction::WordBoundary(*unicode))?,
+            AST::NotWordBoundary(unicode) => self.gen_assert(Instruction::NotWordBoundary(*unicode))?,
+            AST::BackRef(n, case_insensitive) => self.gen_backref(*n, *case_insensitive)?,
+            AST::Atomic(e) => self.gen_atomic(e)?,
+            AST::LookAhead(e, negated) => self.gen_look_around(e, Instruction::LookAhead(*negated, 0))?,
+            AST::LookBehind(e, negated, min, max) => {
+                self.gen_look_around(e, Instruction::LookBehind(*negated, *min, *max, 0))?
+            }
         }
 
         Ok(())
@@ -94,6 +132,48 @@ impl Generator {
         Ok(())
     }
 
+    // 文字を消費しない判定命令のコード生成器
+    fn gen_assert(&mut self, inst: Instruction) -> Result<(), CodeGenError> {
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    fn gen_class(&mut self, class: &CharClass) -> Result<(), CodeGenError> {
+        let inst = Instruction::Class(class.clone());
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    // 非貪欲な量指定子の場合、addr の split の優先度を入れ替える
+    // e.g. split L1, L2 => split L2, L1
+    fn flip_split(&mut self, addr: usize, greedy: bool) {
+        if !greedy {
+            if let Some(Instruction::Split(l1, l2)) = self.insts.get_mut(addr) {
+                std::mem::swap(l1, l2);
+            }
+        }
+    }
+
+    /// キャプチャグループのコード生成器。
+    ///
+    /// n 番目のグループに対して、以下のようなコードを生成
+    ///
+    /// ```text
+    ///     save 2n
+    ///     eのコード
+    ///     save 2n+1
+    /// ```
+    fn gen_capture(&mut self, e: &AST, n: usize) -> Result<(), CodeGenError> {
+        self.insts.push(Instruction::Save(2 * n));
+        self.inc_pc()?;
+        self.gen_expr(e)?;
+        self.insts.push(Instruction::Save(2 * n + 1));
+        self.inc_pc()?;
+        Ok(())
+    }
+
     fn gen_or(&mut self, e1: &AST, e2: &AST) -> Result<(), CodeGenError> {
         // L1とL2に分ける
         let split_addr = self.pc;
@@ -140,7 +220,9 @@ impl Generator {
     /// L1: eのコード
     /// L2:
     /// ```
-    fn gen_question(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    ///
+    /// 非貪欲な ?? の場合は split L2, L1 とし、e を飛ばす方を優先する。
+    fn gen_question(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
         // split L1, L2
         let split_addr = self.pc;
         self.inc_pc()?;
@@ -153,10 +235,11 @@ impl Generator {
         // L2の値を設定
         if let Some(Instruction::Split(_, l2)) = self.insts.get_mut(split_addr) {
             *l2 = self.pc;
-            Ok(())
         } else {
-            Err(CodeGenError::FailQuestion)
+            return Err(CodeGenError::FailQuestion);
         }
+        self.flip_split(split_addr, greedy);
+        Ok(())
     }
 
     /// 以下のようなコードを生成
@@ -166,15 +249,19 @@ impl Generator {
     ///     split L1, L2
     /// L2:
     /// ```
-    fn gen_plus(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    ///
+    /// 非貪欲な +? の場合は split L2, L1 とし、繰り返しを抜ける方を優先する。
+    fn gen_plus(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
         // L1: eのコード
         let l1 = self.pc;
         self.gen_expr(e)?;
 
         // split L1, L2
+        let split_addr = self.pc;
         self.inc_pc()?;
         let split = Instruction::Split(l1, self.pc); // self.pcがL2
         self.insts.push(split);
+        self.flip_split(split_addr, greedy);
 
         Ok(())
     }
@@ -189,7 +276,25 @@ impl Generator {
     ///     jump L1
     /// L3:
     /// ```
-    fn gen_star(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    ///
+    /// 非貪欲な *? の場合は split L3, L2 とし、繰り返しを抜ける方を優先する。
+    ///
+    /// e が空文字列にマッチする場合は、e* を (?:e+)? とみなして以下のようなコードを生成する。
+    ///
+    /// ```text
+    ///     split L1, L2
+    /// L1: eのコード
+    ///     split L1, L2
+    /// L2:
+    /// ```
+    ///
+    /// 空文字列にマッチした e の直後に繰り返しへ戻る場合、評価器はその分岐を捨てる。
+    /// このとき、繰り返しを抜ける分岐が e の中の残りの分岐より優先されるようにするため。
+    fn gen_star(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
+        if match_len(e).0 == 0 {
+            return self.gen_nullable_star(e, greedy);
+        }
+
         // L1: split L2, L3
         let l1 = self.pc;
         self.inc_pc()?;
@@ -206,9 +311,204 @@ impl Generator {
         // L3の値を設定
         if let Some(Instruction::Split(_, l3)) = self.insts.get_mut(l1) {
             *l3 = self.pc;
+        } else {
+            return Err(CodeGenError::FailStar);
+        }
+        self.flip_split(l1, greedy);
+        Ok(())
+    }
+
+    // 空文字列にマッチする e に対する e* のコード生成器
+    fn gen_nullable_star(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
+        // split L1, L2
+        let split_addr = self.pc;
+        self.inc_pc()?;
+        let split = Instruction::Split(self.pc, 0); // self.pcがL1。L2を仮に0と設定
+        self.insts.push(split);
+
+        // L1: eのコード
+        let l1 = self.pc;
+        self.gen_expr(e)?;
+
+        // split L1, L2
+        let loop_addr = self.pc;
+        self.inc_pc()?;
+        let split = Instruction::Split(l1, self.pc); // self.pcがL2
+        self.insts.push(split);
+
+        // L2の値を設定
+        if let Some(Instruction::Split(_, l2)) = self.insts.get_mut(split_addr) {
+            *l2 = self.pc;
+        } else {
+            return Err(CodeGenError::FailStar);
+        }
+        self.flip_split(split_addr, greedy);
+        self.flip_split(loop_addr, greedy);
+        Ok(())
+    }
+
+    fn gen_backref(&mut self, n: usize, case_insensitive: bool) -> Result<(), CodeGenError> {
+        let inst = Instruction::BackRef(n, case_insensitive);
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// 先読み・後読みのコード生成器。
+    ///
+    /// 以下のようなコードを生成
+    ///
+    /// ```text
+    ///     look_ahead L1（または look_behind）
+    ///     eのコード
+    ///     sub_end
+    /// L1:
+    /// ```
+    ///
+    /// inst には L1 を仮に 0 とした look_ahead か look_behind を渡す。
+    fn gen_look_around(&mut self, e: &AST, inst: Instruction) -> Result<(), CodeGenError> {
+        let look_addr = self.pc;
+        self.inc_pc()?;
+        self.insts.push(inst);
+
+        // eのコード
+        self.gen_expr(e)?;
+
+        // sub_end
+        self.inc_pc()?;
+        self.insts.push(Instruction::SubEnd);
+
+        // L1の値を設定
+        match self.insts.get_mut(look_addr) {
+            Some(Instruction::LookAhead(_, l1)) | Some(Instruction::LookBehind(_, _, _, l1)) => {
+                *l1 = self.pc;
+                Ok(())
+            }
+            _ => Err(CodeGenError::FailLookAround),
+        }
+    }
+
+    /// アトミックグループのコード生成器。
+    ///
+    /// 以下のようなコードを生成
+    ///
+    /// ```text
+    ///     atomic L1
+    ///     eのコード
+    ///     sub_end
+    /// L1:
+    /// ```
+    fn gen_atomic(&mut self, e: &AST) -> Result<(), CodeGenError> {
+        let atomic_addr = self.pc;
+        self.inc_pc()?;
+        self.insts.push(Instruction::Atomic(0)); // L1 を仮に0と設定
+
+        // eのコード
+        self.gen_expr(e)?;
+
+        // sub_end
+        self.inc_pc()?;
+        self.insts.push(Instruction::SubEnd);
+
+        // L1の値を設定
+        if let Some(Instruction::Atomic(l1)) = self.insts.get_mut(atomic_addr) {
+            *l1 = self.pc;
             Ok(())
         } else {
-            Err(CodeGenError::FailStar)
+            Err(CodeGenError::FailAtomic)
+        }
+    }
+
+    /// {n,m}限量子のコード生成器。
+    ///
+    /// eを n 回並べたあと、残りの m - n 回を入れ子の ? として展開する。
+    /// e.g. e{2,4} は ee(e(e)?)? と同じ以下のようなコードを生成
+    ///
+    /// ```text
+    ///     eのコード
+    ///     eのコード
+    ///     split L1, L3
+    /// L1: eのコード
+    ///     split L2, L3
+    /// L2: eのコード
+    /// L3:
+    /// ```
+    ///
+    /// 上限がない {n,} の場合は、eを n - 1 回並べたあとに e+ のコードを生成する（n = 0 の場合は e*）。
+    /// 非貪欲な {n,m}? の場合は、各 split の優先度を入れ替える。
+    fn gen_repeat(
+        &mut self,
+        e: &AST,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    ) -> Result<(), CodeGenError> {
+        // eを n 回（上限がない場合は n - 1 回）並べる
+        // 1 回目のコードから e の命令数を求め、残りを並べる前に命令数の上限を調べる
+        // e の命令数が 0 の場合は何回繰り返しても空なので、残りは生成しない
+        let count = if max.is_none() { min.saturating_sub(1) } else { min };
+        if count > 0 {
+            let start = self.pc;
+            self.gen_expr(e)?;
+            let size = self.pc - start;
+            if size == 0 {
+                return Ok(());
+            }
+            self.check_repeat_size(size, count - 1)?;
+            for _ in 1..count {
+                self.gen_expr(e)?;
+            }
+        }
+
+        let max = match max {
+            Some(max) => max,
+            None if min == 0 => return self.gen_star(e, greedy),
+            None => return self.gen_plus(e, greedy),
+        };
+
+        // split Ln, L3 を並べ、L3 は最後にまとめて設定する
+        let mut split_addrs = Vec::new();
+        for i in min..max {
+            let addr = self.pc;
+            split_addrs.push(addr);
+            self.inc_pc()?;
+            let split = Instruction::Split(self.pc, 0); // self.pcがLn。L3を仮に0と設定
+            self.insts.push(split);
+            self.gen_expr(e)?;
+
+            // 1 回目で split と e の命令数を求め、同様に上限を調べる
+            if i == min {
+                let size = self.pc - addr;
+                if size == 1 {
+                    // e が空の場合は split も不要
+                    self.insts.pop();
+                    self.pc = addr;
+                    return Ok(());
+                }
+                self.check_repeat_size(size, max - min - 1)?;
+            }
+        }
+
+        // L3の値を設定
+        for addr in split_addrs {
+            if let Some(Instruction::Split(_, l3)) = self.insts.get_mut(addr) {
+                *l3 = self.pc;
+            } else {
+                return Err(CodeGenError::FailRepeat);
+            }
+            self.flip_split(addr, greedy);
+        }
+
+        Ok(())
+    }
+
+    // 命令数 size のコードを count 回並べた場合に、命令数が上限を超えるかを調べる
+    fn check_repeat_size(&self, size: usize, count: usize) -> Result<(), CodeGenError> {
+        let pc = size.checked_mul(count).and_then(|n| n.checked_add(self.pc));
+        if pc.is_none_or(|pc| pc > self.size_limit) {
+            Err(CodeGenError::SizeLimit(self.size_limit))
+        } else {
+            Ok(())
         }
     }
 
@@ -1,72 +1,389 @@
+use std::cell::Cell;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use crate::engine::{Instruction};
-use crate::engine::evaluator::EvalError::NotSupport;
+use crate::engine::class::{is_case_equivalent, is_word_char};
 use crate::helpers::safe_add;
 
 #[derive(Debug)]
 pub enum EvalError {
     PCOverFlow,
     SPOverFlow,
-    NotSupport,
     InvalidPC, // 評価器の実装に誤りがある場合に発生するエラー
+    NotSupport(&'static str), // 評価器が対応していない命令を含む
     // InvalidContext, // 評価器の実装に誤りがある場合に発生するエラー
 }
 
 impl Display for EvalError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "CodeGEnError: {:?}", self)
+        match self {
+            EvalError::NotSupport(msg) => write!(f, "EvalError: not supported: {msg}"),
+            _ => write!(f, "CodeGEnError: {:?}", self),
+        }
     }
 }
 
 impl Error for EvalError{}
 
+// 評価の対象となる命令列と文字列
+struct Context<'a> {
+    inst: &'a [Instruction],
+    line: &'a [char],
+    index: usize, // line が元の文字列の何文字目から始まるか
+    look_end: Option<usize>, // 後読みの式を評価中の場合、式が終わるべき位置
+    sub_end: Option<&'a Cell<usize>>, // アトミックグループの式を評価中の場合、式が終わった位置を記録する場所
+}
+
+impl Context<'_> {
+    // 位置 sp が単語の境界（前後の一方だけが単語を構成する文字）かを返す
+    // 文字列の外側は単語を構成しない文字とみなす
+    fn is_word_boundary(&self, sp: usize, unicode: bool) -> bool {
+        let is_word = |i: Option<usize>| {
+            i.and_then(|i| self.line.get(i)).is_some_and(|c| is_word_char(*c, unicode))
+        };
+        is_word(sp.checked_sub(1)) != is_word(Some(sp))
+    }
+
+    // 文字を消費しない判定命令（^ や $ や \b）が、位置 sp で成り立つかを返す
+    fn is_assert(&self, inst: &Instruction, sp: usize) -> Result<bool, EvalError> {
+        let is_start = self.index == 0 && sp == 0;
+        let is_end = sp == self.line.len();
+        match inst {
+            Instruction::Caret => Ok(is_start),  // 文字列の先頭
+            Instruction::Dollar => Ok(is_end),   // 文字列の末尾
+            Instruction::LineStart(crlf) => {
+                // 文字列の先頭か、改行の直後
+                // \r\n を行の区切りとする場合は \r の直後も含むが、\r と \n の間は含まない
+                let prev = sp.checked_sub(1).and_then(|i| self.line.get(i));
+                let is_after_cr = *crlf && prev == Some(&'\r') && self.line.get(sp) != Some(&'\n');
+                Ok(is_start || prev == Some(&'\n') || is_after_cr)
+            }
+            Instruction::LineEnd(crlf) => {
+                // 文字列の末尾か、改行の直前
+                // \r\n を行の区切りとする場合は \r の直前も含むが、\r と \n の間は含まない
+                let prev = sp.checked_sub(1).and_then(|i| self.line.get(i));
+                let next = self.line.get(sp);
+                if *crlf {
+                    Ok(is_end || next == Some(&'\r') || (next == Some(&'\n') && prev != Some(&'\r')))
+                } else {
+                    Ok(is_end || next == Some(&'\n'))
+                }
+            }
+            Instruction::WordBoundary(unicode) => Ok(self.is_word_boundary(sp, *unicode)),
+            Instruction::NotWordBoundary(unicode) => Ok(!self.is_word_boundary(sp, *unicode)),
+            _ => Err(EvalError::InvalidPC),
+        }
+    }
+}
+
 pub fn eval(inst: &[Instruction], line: &[char], index: usize, is_depth: bool) -> Result<bool, EvalError> {
+    let ctx = Context { inst, line, index, look_end: None, sub_end: None };
     if is_depth {
-        eval_depth(inst, line, 0, 0, index)
+        // 後方参照のために、キャプチャの位置を記録しておく
+        let slots_len = inst
+            .iter()
+            .filter_map(|i| if let Instruction::Save(n) = i { Some(n + 1) } else { None })
+            .max()
+            .unwrap_or(0);
+        eval_depth(&ctx, 0, 0, &mut vec![None; slots_len])
     } else {
-        Ok(false) // 一旦対応しない
+        eval_width(&ctx, 0, true, &mut [])
     }
 }
 
-/// 深さ優先探索で再帰的にマッチングを行う関数
-fn eval_depth(
+/// line の start 文字目以降で、最初にマッチする箇所を探索する関数
+///
+/// マッチした場合は、キャプチャの開始位置と終了位置（文字単位）を slots に設定する。
+/// slots[2n], slots[2n + 1] が n 番目のキャプチャの開始位置と終了位置で、
+/// slots[0], slots[1] はマッチ全体の開始位置と終了位置。
+/// 位置が不要な場合は、空の slots を渡す。
+pub fn search(
     inst: &[Instruction],
     line: &[char],
+    start: usize,
+    is_depth: bool,
+    slots: &mut [Option<usize>],
+) -> Result<bool, EvalError> {
+    let ctx = Context { inst, line, index: 0, look_end: None, sub_end: None };
+    if is_depth {
+        // 開始位置をずらしながら、先頭からのマッチングを繰り返す
+        for sp in start..=line.len() {
+            slots.fill(None);
+            if let Some(s) = slots.get_mut(0) {
+                *s = Some(sp);
+            }
+            if eval_depth(&ctx, 0, sp, slots)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    } else {
+        eval_width(&ctx, start, false, slots)
+    }
+}
+
+/// 命令列を幅優先探索で評価できるかを調べる関数
+///
+/// 先読み・後読み、後方参照とアトミックグループは深さ優先探索でのみ評価できるため、含まれる場合はエラー。
+pub fn check_width(inst: &[Instruction]) -> Result<(), EvalError> {
+    for i in inst {
+        match i {
+            Instruction::LookAhead(..) | Instruction::LookBehind(..) | Instruction::SubEnd => {
+                return Err(EvalError::NotSupport("look-around assertions require the depth-first evaluator"))
+            }
+            Instruction::BackRef(..) => {
+                return Err(EvalError::NotSupport("backreferences require the depth-first evaluator"))
+            }
+            Instruction::Atomic(..) => {
+                return Err(EvalError::NotSupport(
+                    "atomic groups and possessive quantifiers require the depth-first evaluator",
+                ))
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+// 幅優先探索のスレッド
+struct Thread {
+    pc: usize,
+    slots: Vec<Option<usize>>, // このスレッドで記録したキャプチャの位置
+}
+
+/// 幅優先探索（Pike VM）でマッチングを行う関数
+///
+/// 実行中のスレッド（プログラムカウンタ）の集合を保持し、1文字ずつ全スレッドを同時に進める。
+/// 同じ位置で同じプログラムカウンタを持つスレッドは 1 つにまとめるため、
+/// 命令数を m、文字列長を n とすると O(mn) で評価できる。
+///
+/// anchored が false の場合は、マッチが見つかるまで各位置から新しいスレッドを開始するため、
+/// 開始位置をずらしながら評価し直すことなく、最初にマッチする箇所を探索できる。
+fn eval_width(
+    ctx: &Context,
+    start: usize,
+    anchored: bool,
+    slots: &mut [Option<usize>],
+) -> Result<bool, EvalError> {
+    let mut visited = vec![false; ctx.inst.len()]; // 現在の位置で追加済みのプログラムカウンタ
+    let mut clist = Vec::new(); // 現在の位置で実行するスレッド
+    let mut is_match = false;
+
+    for sp in start..=ctx.line.len() {
+        // 現在の位置から新しいスレッドを開始
+        // 既存のスレッドより開始位置が後ろなので、優先度は最も低い
+        if !is_match && (sp == start || !anchored) {
+            let mut init = vec![None; slots.len()];
+            if let Some(s) = init.get_mut(0) {
+                *s = Some(sp);
+            }
+            add_thread(ctx, &mut clist, &mut visited, 0, sp, init)?;
+        }
+
+        if clist.is_empty() && (is_match || anchored) {
+            break;
+        }
+
+        let mut nlist = Vec::new(); // 次の位置で実行するスレッド
+        visited.fill(false);
+
+        for thread in clist {
+            let next = if let Some(i) = ctx.inst.get(thread.pc) {
+                i
+            } else {
+                return Err(EvalError::InvalidPC)
+            };
+
+            // add_thread により、ここには文字を消費する命令か Match しか来ない
+            let is_step = match next {
+                Instruction::Match => {
+                    // マッチした位置を記録
+                    slots.copy_from_slice(&thread.slots);
+                    if let Some(end) = slots.get_mut(1) {
+                        *end = Some(sp);
+                    } else {
+                        // 位置が不要な場合はここで終了してよい
+                        return Ok(true);
+                    }
+                    is_match = true;
+
+                    // このスレッドより優先度の低いスレッドは捨て、
+                    // 優先度の高いスレッドがより長くマッチするかを引き続き調べる
+                    break;
+                }
+                Instruction::Char(c) => ctx.line.get(sp) == Some(c),
+                Instruction::Dot => sp < ctx.line.len(),
+                Instruction::Class(class) => ctx.line.get(sp).is_some_and(|c| class.contains(*c)),
+                _ => return Err(EvalError::InvalidPC),
+            };
+
+            if is_step {
+                add_thread(ctx, &mut nlist, &mut visited, thread.pc + 1, sp + 1, thread.slots)?;
+            }
+        }
+
+        clist = nlist;
+    }
+
+    Ok(is_match)
+}
+
+/// 文字を消費しない命令（jump, split, save と ^ や $ などの判定）を辿り、スレッドをリストに追加する関数
+///
+/// split は L1 を先に辿ることで、リスト内のスレッドが優先度順に並ぶようにする。
+/// 再帰の代わりに、後で辿る L2 をスタックに積むため、命令数が多くてもスタックオーバーフローしない。
+fn add_thread(
+    ctx: &Context,
+    list: &mut Vec<Thread>,
+    visited: &mut [bool],
+    pc: usize,
+    sp: usize,
+    slots: Vec<Option<usize>>,
+) -> Result<(), EvalError> {
+    let mut stack = vec![(pc, slots)];
+
+    while let Some((mut pc, mut slots)) = stack.pop() {
+        loop {
+            match visited.get_mut(pc) {
+                Some(true) => break, // 同じ位置で既に追加済み
+                Some(v) => *v = true,
+                None => return Err(EvalError::InvalidPC),
+            }
+
+            match &ctx.inst[pc] {
+                Instruction::Jump(addr) => pc = *addr,
+                Instruction::Split(addr1, addr2) => {
+                    stack.push((*addr2, slots.clone()));
+                    pc = *addr1;
+                }
+                Instruction::Caret
+                | Instruction::Dollar
+                | Instruction::LineStart(_)
+                | Instruction::LineEnd(_)
+                | Instruction::WordBoundary(_)
+                | Instruction::NotWordBoundary(_) => {
+                    // 判定が成り立つ場合のみ、文字を消費せずに次の命令へ進む
+                    if !ctx.is_assert(&ctx.inst[pc], sp)? {
+                        break;
+                    }
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                }
+                Instruction::Save(n) => {
+                    if let Some(slot) = slots.get_mut(*n) {
+                        *slot = Some(sp);
+                    }
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                }
+                Instruction::LookAhead(..)
+                | Instruction::LookBehind(..)
+                | Instruction::SubEnd
+                | Instruction::BackRef(..)
+                | Instruction::Atomic(..) => return check_width(ctx.inst),
+                _ => {
+                    list.push(Thread { pc, slots });
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// 深さ優先探索で、評価が失敗した場合に戻る地点
+enum Backtrack {
+    Branch(usize, usize),        // split のもう一方の分岐の (pc, sp) から評価を再開する
+    Slot(usize, Option<usize>),  // n 番目のキャプチャの位置を元に戻す
+    Slots(Vec<Option<usize>>),   // 先読み・後読み・アトミックグループを評価する前のキャプチャに戻す
+    Visit(usize, Option<usize>), // split を最後に通過した位置を元に戻す
+}
+
+/// 深さ優先探索でマッチングを行う関数
+///
+/// 再帰の代わりにバックトラックする地点をスタックに積むため、長い文字列でもスタックオーバーフローしない。
+/// また、空文字列にマッチする式の繰り返しで無限ループしないように、split を最後に通過した位置を記録し、
+/// 文字を消費せずに同じ split へ戻ってきた場合（空の繰り返し）は失敗とする。
+fn eval_depth(
+    ctx: &Context,
+    pc: usize,
+    sp: usize,
+    slots: &mut [Option<usize>],
+) -> Result<bool, EvalError> {
+    let mut stack = vec![Backtrack::Branch(pc, sp)];
+    let mut visits = vec![None; ctx.inst.len()];
+
+    while let Some(backtrack) = stack.pop() {
+        let (pc, sp) = match backtrack {
+            Backtrack::Branch(pc, sp) => (pc, sp),
+            Backtrack::Slot(n, prev) => {
+                if let Some(slot) = slots.get_mut(n) {
+                    *slot = prev;
+                }
+                continue;
+            }
+            Backtrack::Slots(saved) => {
+                slots.copy_from_slice(&saved);
+                continue;
+            }
+            Backtrack::Visit(pc, prev) => {
+                if let Some(visit) = visits.get_mut(pc) {
+                    *visit = prev;
+                }
+                continue;
+            }
+        };
+
+        if eval_branch(ctx, pc, sp, slots, &mut stack, &mut visits)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// 深さ優先探索で、(pc, sp) から失敗するまで評価を進める関数
+///
+/// split ではもう一方の分岐を、キャプチャの記録では元の位置をスタックに積み、失敗した場合に戻れるようにする。
+fn eval_branch(
+    ctx: &Context,
     mut pc: usize,
     mut sp: usize,
-    index: usize,
+    slots: &mut [Option<usize>],
+    stack: &mut Vec<Backtrack>,
+    visits: &mut [Option<usize>],
 ) -> Result<bool, EvalError> {
     loop {
-        let next = if let Some(i) = inst.get(pc) {
+        let next = if let Some(i) = ctx.inst.get(pc) {
             i
         } else {
             return Err(EvalError::InvalidPC)
         };
 
         match next {
-            Instruction::Dollar => {
-                if sp == line.len() {
-                    return Ok(true)
+            Instruction::Caret
+            | Instruction::Dollar
+            | Instruction::LineStart(_)
+            | Instruction::LineEnd(_)
+            | Instruction::WordBoundary(_)
+            | Instruction::NotWordBoundary(_) => {
+                // 文字を消費しない判定なので、成り立つ場合は sp を進めずに次の命令へ
+                if ctx.is_assert(next, sp)? {
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
                 } else {
                     return Ok(false)
                 }
             }
-            Instruction::Caret => {
-                if pc != 0 {
-                    return Err(NotSupport)
-                }
-                if index != 0 {
-                    return Ok(false)
-                }
-                safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-            }
             Instruction::Dot => {
-                safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-                safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
+                if sp < ctx.line.len() {
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
+                } else {
+                    // 最後まで来てしまったので false
+                    return Ok(false);
+                }
             }
             Instruction::Char(c) => {
-                if let Some(sp_c) = line.get(sp) {
+                if let Some(sp_c) = ctx.line.get(sp) {
                     if c == sp_c {
                         // 一致した場合、次の評価
                         safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
@@ -80,19 +397,142 @@ fn eval_depth(
                     return Ok(false);
                 }
             }
+            Instruction::Class(class) => {
+                if ctx.line.get(sp).is_some_and(|c| class.contains(*c)) {
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
+                } else {
+                    return Ok(false)
+                }
+            }
+            Instruction::Save(n) => {
+                // 後続の評価が失敗した場合は、元の位置に戻す
+                if let Some(slot) = slots.get_mut(*n) {
+                    stack.push(Backtrack::Slot(*n, slot.replace(sp)));
+                }
+                safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+            }
             Instruction::Match => {
+                if let Some(end) = slots.get_mut(1) {
+                    *end = Some(sp);
+                }
                 return Ok(true);
             }
             Instruction::Jump(addr) => {
                 pc = *addr
             }
-            Instruction::Split(addr1, addr2) => {
-                return if eval_depth(inst, line, *addr1, sp, index)? || eval_depth(inst, line, *addr2, sp, index)? {
-                    Ok(true)
+            Instruction::LookAhead(negated, next) => {
+                // 現在の位置から先読みの式を評価する
+                let saved = slots.to_vec();
+                let sub = Context { look_end: None, sub_end: None, ..*ctx };
+                let is_match = eval_depth(&sub, pc + 1, sp, slots)?;
+                if !after_look(is_match != *negated, slots, saved, stack) {
+                    return Ok(false);
+                }
+                pc = *next;
+            }
+            Instruction::LookBehind(negated, min, max, next) => {
+                // min 以上 max 以下の文字数だけ戻った位置から、現在の位置で終わるように後読みの式を評価する
+                // 文字列の先頭までの文字数が min に満たない場合は、マッチしない
+                let saved = slots.to_vec();
+                let sub = Context { look_end: Some(sp), sub_end: None, ..*ctx };
+                let mut is_match = false;
+                if let Some(last) = sp.checked_sub(*min) {
+                    for start in sp.saturating_sub(*max)..=last {
+                        if eval_depth(&sub, pc + 1, start, slots)? {
+                            is_match = true;
+                            break;
+                        }
+                    }
+                }
+                if !after_look(is_match != *negated, slots, saved, stack) {
+                    return Ok(false);
+                }
+                pc = *next;
+            }
+            Instruction::BackRef(n, case_insensitive) => {
+                // n 番目のグループにマッチした部分文字列と、現在の位置からの文字列を比較する
+                // グループがマッチに参加していない場合は失敗
+                // 繰り返しの中でグループ自身を参照した場合など、開始位置だけが更新されて終了位置より後ろにある場合も失敗
+                let (start, end) = match (slots.get(2 * n), slots.get(2 * n + 1)) {
+                    (Some(Some(start)), Some(Some(end))) if start <= end => (*start, *end),
+                    _ => return Ok(false),
+                };
+                let len = end - start;
+                let mut target_end = sp;
+                safe_add(&mut target_end, &len, || EvalError::SPOverFlow)?;
+                let target = match ctx.line.get(sp..target_end) {
+                    Some(target) => target,
+                    None => return Ok(false),
+                };
+                let is_equal = if *case_insensitive {
+                    ctx.line[start..end].iter().zip(target).all(|(a, b)| is_case_equivalent(*a, *b))
                 } else {
-                    Ok(false)
+                    ctx.line[start..end] == *target
+                };
+                if !is_equal {
+                    return Ok(false);
                 }
+                safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                safe_add(&mut sp, &len, || EvalError::SPOverFlow)?;
+            }
+            Instruction::Atomic(next) => {
+                // グループの式が最初にマッチした位置から後続を評価し、
+                // 後続が失敗してもグループの式の別のマッチ方法は試さない（バックトラックの打ち切り）
+                let saved = slots.to_vec();
+                let end = Cell::new(sp);
+                let sub = Context { look_end: None, sub_end: Some(&end), ..*ctx };
+                let is_match = eval_depth(&sub, pc + 1, sp, slots)?;
+                if !after_look(is_match, slots, saved, stack) {
+                    return Ok(false);
+                }
+                pc = *next;
+                sp = end.get();
+            }
+            Instruction::SubEnd => {
+                // 後読みの場合は、後読みを開始した位置で終わっていなければならない
+                if ctx.look_end.is_some_and(|end| end != sp) {
+                    return Ok(false);
+                }
+                // アトミックグループの場合は、終わった位置を記録する
+                if let Some(end) = ctx.sub_end {
+                    end.set(sp);
+                }
+                return Ok(true);
+            }
+            Instruction::Split(addr1, addr2) => {
+                // 文字を消費せずに同じ split へ戻ってきた場合は、空の繰り返しなので失敗
+                let visit = match visits.get_mut(pc) {
+                    Some(visit) => visit,
+                    None => return Err(EvalError::InvalidPC),
+                };
+                if *visit == Some(sp) {
+                    return Ok(false);
+                }
+                stack.push(Backtrack::Visit(pc, visit.replace(sp)));
+
+                // addr1 を先に評価し、失敗した場合は addr2 から評価する
+                stack.push(Backtrack::Branch(*addr2, sp));
+                pc = *addr1;
             }
         }
     }
 }
+
+/// 先読み・後読み・アトミックグループの判定結果に応じて、後続の評価を続けるかを返す関数
+///
+/// これらの式はバックトラックしない（一度成り立てば、別のマッチ方法は試さない）。
+/// 式の中で記録したキャプチャは、判定が成り立たない場合や後続の評価が失敗した場合に saved の状態へ戻す。
+fn after_look(
+    is_success: bool,
+    slots: &mut [Option<usize>],
+    saved: Vec<Option<usize>>,
+    stack: &mut Vec<Backtrack>,
+) -> bool {
+    if is_success {
+        stack.push(Backtrack::Slots(saved));
+    } else {
+        slots.copy_from_slice(&saved);
+    }
+    is_success
+}
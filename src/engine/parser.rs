@@ -1,22 +1,27 @@
 //! 正規表現の式をパースし、抽象構文木に変換
 //  ↑ cargo doc でドキュメント化される
 use std::{
+    collections::HashMap,
     error::Error, // エラー用の型を規定するためのトレイト
     fmt::{self, Display}, // println! マクロなどで表示するためのトレイト
+    iter::{Enumerate, Peekable},
     mem::take, // ある変数から所有権の取得し、その変数の初期化を同時に行う関数
+    str::Chars,
 };
 use std::fmt::Formatter;
+use crate::engine::class::CharClass;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum AST {
     Char(char),
     // 1文字パターン
-    Plus(Box<AST>),
-    // +
-    Star(Box<AST>),
-    // *
-    Question(Box<AST>),
-    // ?
+    Plus(Box<AST>, bool),
+    // + （bool は貪欲かどうか。+? の場合は false）
+    Star(Box<AST>, bool),
+    // * （*? の場合は false）
+    Question(Box<AST>, bool),
+    // ? （?? の場合は false）
     Or(Box<AST>, Box<AST>),
     // |
     Seq(Vec<AST>), // 正規表現の列
@@ -24,6 +29,44 @@ pub enum AST {
     Dot,
     Caret,
     Dollar,
+    Class(CharClass), // [a-z0-9_] や [^"] などの文字クラス
+    Repeat(Box<AST>, usize, Option<usize>, bool), // {n,m} 最大回数が None の場合は上限なし（{n,m}? の場合は非貪欲）
+    Capture(Box<AST>, usize, Option<String>), // (abc) キャプチャグループと、その番号（1 から始まる）と名前
+    LineStart(bool), // マルチラインモードの ^（bool は \r\n を行の区切りとするかどうか）
+    LineEnd(bool),   // マルチラインモードの $
+    WordBoundary(bool),    // \b（bool は Unicode の単語の定義を使うかどうか）
+    NotWordBoundary(bool), // \B
+    LookAhead(Box<AST>, bool), // (?=abc) 先読み（bool は否定 (?!abc) かどうか）
+    LookBehind(Box<AST>, bool, usize, usize), // (?<=abc) 後読み（否定 (?<!abc) かどうか, 最小の長さ, 最大の長さ）
+    BackRef(usize, bool), // \1 や \k<name> 後方参照（グループの番号, 大文字・小文字を同一視するかどうか）
+    Atomic(Box<AST>), // (?>abc) アトミックグループ。a*+ などの強欲な量指定子もこれで表す
+}
+
+/// パース時に適用するフラグ
+///
+/// 正規表現中の (?m) などで、グループの残りの部分に対して変更できる。
+/// (?m:...) の場合はグループの中でのみ変更する。
+#[derive(Debug, Clone, Copy)]
+pub struct Flags {
+    pub multi_line: bool,       // m: ^ と $ を各行の先頭と末尾にもマッチさせる
+    pub case_insensitive: bool, // i: 大文字・小文字を同一視する
+    pub unicode: bool,          // u: \d, \w, \s を Unicode の定義にする（false の場合は ASCII のみ）
+    pub dot_all: bool,          // s: . を改行にもマッチさせる
+    pub extended: bool,         // x: 空白と # から行末までのコメントを無視する
+    pub crlf: bool,             // R: 行の区切りを \n に加えて \r\n とする（. と、マルチラインモードの ^ と $ に影響）
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags {
+            multi_line: false,
+            case_insensitive: false,
+            unicode: true,
+            dot_all: false,
+            extended: false,
+            crlf: false,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -36,6 +79,40 @@ pub enum ParseError {
     // +, |, *, ? の前に式がない
     NoRightParen,
     // 閉じカッコなし
+    NoRightBracket(usize),
+    // 文字クラスの閉じ角カッコなし（位置は開き角カッコ）
+    EmptyClass(usize),
+    // 文字クラスが空 e.g. []
+    InvalidRange(usize, char, char),
+    // 文字クラスの範囲の始点が終点より大きい e.g. [z-a]
+    NoRightBrace(usize),
+    // 繰り返し回数の閉じ波カッコなし（位置は開き波カッコ）
+    InvalidRepeat(usize),
+    // 繰り返し回数の書式が不正 e.g. a{x}
+    InvalidRepeatRange(usize, usize, usize),
+    // 繰り返し回数の最小値が最大値より大きい e.g. a{3,1}
+    UnknownFlag(usize, char),
+    // 不明なフラグ e.g. (?q)
+    InvalidFlags(usize),
+    // フラグの書式が不正 e.g. (?), (?i-), (?-i-m)
+    ClassRangeEndpoint(usize),
+    // 文字クラスの範囲の端点に \d などを指定 e.g. [a-\d]
+    InvalidHexEscape(usize),
+    // 16 進数のエスケープの書式が不正 e.g. \xZZ, \x{}
+    SurrogateCodePoint(usize, u32),
+    // サロゲートのコードポイント e.g. \u{D800}
+    CodePointOutOfRange(usize),
+    // 範囲外のコードポイント e.g. \x{110000}
+    UnknownProperty(usize, String),
+    // 不明な Unicode の性質 e.g. \p{Foo}
+    InvalidGroupName(usize),
+    // グループの名前が不正 e.g. (?P<1a>...), (?P<>...)（位置は開きカッコ）
+    DuplicateGroupName(usize, String),
+    // グループの名前が重複 e.g. (?P<a>x)(?P<a>y)（位置は後の開きカッコ）
+    UnboundedLookBehind(usize),
+    // 後読みの長さに上限がない e.g. (?<=a+)（位置は開きカッコ）
+    UnknownGroupRef(usize, String),
+    // 後方参照するグループが存在しない e.g. (a)\2, \k<none>
     Empty,                      // 空
 }
 
@@ -54,6 +131,57 @@ impl Display for ParseError {
             ParseError::NoRightParen => {
                 write!(f, "ParseError: no right parenthesis")
             }
+            ParseError::NoRightBracket(pos) => {
+                write!(f, "ParseError: no right bracket: pos = {pos}")
+            }
+            ParseError::EmptyClass(pos) => {
+                write!(f, "ParseError: empty character class: pos = {pos}")
+            }
+            ParseError::InvalidRange(pos, start, end) => {
+                write!(f, "ParseError: invalid range: pos = {pos}, range = '{start}-{end}'")
+            }
+            ParseError::NoRightBrace(pos) => {
+                write!(f, "ParseError: no right brace: pos = {pos}")
+            }
+            ParseError::InvalidRepeat(pos) => {
+                write!(f, "ParseError: invalid repetition: pos = {pos}")
+            }
+            ParseError::InvalidRepeatRange(pos, min, max) => {
+                write!(f, "ParseError: invalid repetition range: pos = {pos}, range = {{{min},{max}}}")
+            }
+            ParseError::UnknownFlag(pos, c) => {
+                write!(f, "ParseError: unknown flag: pos = {pos}, char = '{c}'")
+            }
+            ParseError::InvalidFlags(pos) => {
+                write!(f, "ParseError: invalid flags: pos = {pos}")
+            }
+            ParseError::ClassRangeEndpoint(pos) => {
+                write!(f, "ParseError: character class cannot be a range endpoint: pos = {pos}")
+            }
+            ParseError::InvalidHexEscape(pos) => {
+                write!(f, "ParseError: invalid hex escape: pos = {pos}")
+            }
+            ParseError::SurrogateCodePoint(pos, cp) => {
+                write!(f, "ParseError: surrogate code point: pos = {pos}, code point = U+{cp:04X}")
+            }
+            ParseError::CodePointOutOfRange(pos) => {
+                write!(f, "ParseError: code point out of range (max U+10FFFF): pos = {pos}")
+            }
+            ParseError::UnknownProperty(pos, name) => {
+                write!(f, "ParseError: unknown property: pos = {pos}, name = '{name}'")
+            }
+            ParseError::InvalidGroupName(pos) => {
+                write!(f, "ParseError: invalid group name: pos = {pos}")
+            }
+            ParseError::DuplicateGroupName(pos, name) => {
+                write!(f, "ParseError: duplicate group name: pos = {pos}, name = '{name}'")
+            }
+            ParseError::UnboundedLookBehind(pos) => {
+                write!(f, "ParseError: look-behind must have a bounded length: pos = {pos}")
+            }
+            ParseError::UnknownGroupRef(pos, name) => {
+                write!(f, "ParseError: reference to undefined group: pos = {pos}, group = '{name}'")
+            }
             ParseError::Empty => write!(f, "ParseError: empty expression"),
         }
     }
@@ -61,35 +189,293 @@ impl Display for ParseError {
 
 impl Error for ParseError {}
 
+// 式を 1 文字ずつ読み進めるためのイテレータ（文字の位置, 文字）
+type ExprChars<'a> = Peekable<Enumerate<Chars<'a>>>;
+
+// エスケープシーケンスが表す文字、または文字クラス
+enum Escaped {
+    Char(char),
+    Class(CharClass),
+}
+
+// \x や \u の次の文字から、16 進数のコードポイントを読んで文字に変換する
+// \xHH は 2 桁ちょうど、\x{...} と \u{...} は 1 桁以上
+// pos: x や u の位置
+// braced: 波カッコで囲む書式のみを許す場合は true
+fn parse_hex(chars: &mut ExprChars, pos: usize, braced: bool) -> Result<char, ParseError> {
+    let mut cp: Option<u32> = Some(0); // 桁あふれした場合は None
+    if let Some((brace, _)) = chars.next_if(|(_, c)| *c == '{') {
+        let mut digits = 0;
+        while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_hexdigit()) {
+            let d = c.to_digit(16).unwrap();
+            cp = cp.and_then(|n| n.checked_mul(16)).and_then(|n| n.checked_add(d));
+            digits += 1;
+        }
+        match chars.next() {
+            Some((_, '}')) if digits > 0 => (),
+            Some(_) => return Err(ParseError::InvalidHexEscape(pos)),
+            None => return Err(ParseError::NoRightBrace(brace)),
+        }
+    } else if braced {
+        return Err(ParseError::InvalidHexEscape(pos));
+    } else {
+        for _ in 0..2 {
+            let (_, c) = chars
+                .next_if(|(_, c)| c.is_ascii_hexdigit())
+                .ok_or(ParseError::InvalidHexEscape(pos))?;
+            cp = cp.map(|n| n * 16 + c.to_digit(16).unwrap());
+        }
+    }
+
+    let cp = cp.ok_or(ParseError::CodePointOutOfRange(pos))?;
+    match char::from_u32(cp) {
+        Some(c) => Ok(c),
+        None if (0xD800..=0xDFFF).contains(&cp) => Err(ParseError::SurrogateCodePoint(pos, cp)),
+        None => Err(ParseError::CodePointOutOfRange(pos)),
+    }
+}
+
+// \p や \P の次の文字から、Unicode の性質の名前を読んで文字クラスに変換する
+// 名前が 1 文字の場合は波カッコを省略できる e.g. \pL, \p{Lu}, \p{Greek}
+// pos: p や P の位置
+fn parse_property(chars: &mut ExprChars, pos: usize) -> Result<CharClass, ParseError> {
+    let name = if let Some((brace, _)) = chars.next_if(|(_, c)| *c == '{') {
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '}')) => break,
+                Some((_, c)) => name.push(c),
+                None => return Err(ParseError::NoRightBrace(brace)),
+            }
+        }
+        name
+    } else {
+        chars.next().map(|(_, c)| c.to_string()).unwrap_or_default()
+    };
+
+    CharClass::unicode_property(&name).ok_or(ParseError::UnknownProperty(pos, name))
+}
+
+// 文字クラスの内外で共通のエスケープシーケンスを処理する
+// 制御文字 e.g. \t => Escaped::Char('\t')
+// コードポイント e.g. \x41, \x{1F600}, \u{3042} => Escaped::Char('A')
+// 定義済みの文字クラス e.g. \d => Escaped::Class([0-9])、大文字は否定 e.g. \D => [^0-9]
+// Unicode の性質 e.g. \p{Greek}、\P は否定
+// 該当しない場合は None
+// pos: 現在の文字の位置
+fn parse_common_escape(
+    chars: &mut ExprChars,
+    pos: usize,
+    c: char,
+    flags: &Flags,
+) -> Result<Option<Escaped>, ParseError> {
+    let class = match c {
+        'x' => return Ok(Some(Escaped::Char(parse_hex(chars, pos, false)?))),
+        'u' => return Ok(Some(Escaped::Char(parse_hex(chars, pos, true)?))),
+        't' => return Ok(Some(Escaped::Char('\t'))),
+        'n' => return Ok(Some(Escaped::Char('\n'))),
+        'r' => return Ok(Some(Escaped::Char('\r'))),
+        'f' => return Ok(Some(Escaped::Char('\u{C}'))),
+        'v' => return Ok(Some(Escaped::Char('\u{B}'))),
+        '0' => return Ok(Some(Escaped::Char('\0'))),
+        'd' | 'D' => CharClass::perl_digit(flags.unicode),
+        's' | 'S' => CharClass::perl_space(flags.unicode),
+        'w' | 'W' => CharClass::perl_word(flags.unicode),
+        'p' | 'P' => parse_property(chars, pos)?,
+        _ => return Ok(None),
+    };
+
+    // 大文字・小文字の同一視は否定の前に行う
+    // e.g. (?i)\P{Lu} は大文字にも小文字にもマッチしない
+    let class = if flags.case_insensitive { class.case_fold() } else { class };
+    if c.is_ascii_uppercase() {
+        Ok(Some(Escaped::Class(class.negate())))
+    } else {
+        Ok(Some(Escaped::Class(class)))
+    }
+}
+
 // pos: 現在の文字の位置
 // c: エスケープする特殊文字
-fn parse_escape(pos: usize, c: char) -> Result<AST, ParseError> {
+fn parse_escape(chars: &mut ExprChars, pos: usize, c: char, flags: &Flags) -> Result<AST, ParseError> {
     match c {
-        '^' | '$' | '.' | '\\' | '(' | ')' | '|' | '+' | '*' | '?' => Ok(AST::Char(c)),
-        _ => {
-            let err = ParseError::InvalidEscape(pos, c);
-            Err(err)
+        '^' | '$' | '.' | '\\' | '(' | ')' | '|' | '+' | '*' | '?' | '[' | ']' | '{' | '}' => {
+            Ok(AST::Char(c))
+        }
+        ' ' | '#' => Ok(AST::Char(c)), // 拡張モードで空白や # そのものにマッチさせる場合に使う
+        'A' => Ok(AST::Caret),  // フラグによらず文字列の先頭
+        'z' => Ok(AST::Dollar), // フラグによらず文字列の末尾
+        'b' => Ok(AST::WordBoundary(flags.unicode)),
+        'B' => Ok(AST::NotWordBoundary(flags.unicode)),
+        _ => match parse_common_escape(chars, pos, c, flags)? {
+            Some(Escaped::Char(c)) => Ok(parse_char(c, flags)),
+            Some(Escaped::Class(class)) => Ok(AST::Class(class)),
+            None => Err(ParseError::InvalidEscape(pos, c)),
+        },
+    }
+}
+
+// 1文字パターンを AST に変換する
+// 大文字・小文字を同一視する場合は、同一視される文字をまとめた文字クラスにする
+// e.g. (?i)a => AST::Class([('A', 'A'), ('a', 'a')])
+fn parse_char(c: char, flags: &Flags) -> AST {
+    if flags.case_insensitive {
+        let single = CharClass::new(vec![(c, c)]);
+        let folded = single.case_fold();
+        if folded != single {
+            return AST::Class(folded);
         }
     }
+    AST::Char(c)
+}
+
+// . を AST に変換する
+// ドットオールモードでは任意の 1 文字、そうでなければ改行以外の 1 文字
+// \r\n を行の区切りとする場合は、\r も除く
+fn parse_dot(flags: &Flags) -> AST {
+    if flags.dot_all {
+        AST::Dot
+    } else if flags.crlf {
+        AST::Class(CharClass::new(vec![('\n', '\n'), ('\r', '\r')]).negate())
+    } else {
+        AST::Class(CharClass::new(vec![('\n', '\n')]).negate())
+    }
+}
+
+// 文字クラス内のエスケープシーケンスを処理する
+// pos: 現在の文字の位置
+// c: エスケープする特殊文字
+fn parse_class_escape(
+    chars: &mut ExprChars,
+    pos: usize,
+    c: char,
+    flags: &Flags,
+) -> Result<Escaped, ParseError> {
+    match c {
+        '\\' | '[' | ']' | '^' | '-' => Ok(Escaped::Char(c)),
+        _ => parse_common_escape(chars, pos, c, flags)?.ok_or(ParseError::InvalidEscape(pos, c)),
+    }
+}
+
+// 文字クラスの要素を 1 つ読む
+// エスケープされた文字はそのまま文字として扱い、\d などは文字クラスとして返す
+fn parse_class_item(
+    chars: &mut ExprChars,
+    pos: usize,
+    flags: &Flags,
+) -> Result<(usize, Escaped), ParseError> {
+    match chars.next() {
+        Some((i, '\\')) => match chars.next() {
+            Some((j, c)) => Ok((i, parse_class_escape(chars, j, c, flags)?)),
+            None => Err(ParseError::NoRightBracket(pos)),
+        },
+        Some((i, c)) => Ok((i, Escaped::Char(c))),
+        None => Err(ParseError::NoRightBracket(pos)),
+    }
+}
+
+// [ の次の文字から ] までを読み、文字クラスに変換する
+// e.g. [a-z0-9_] => AST::Class([('0', '9'), ('_', '_'), ('a', 'z')])
+// pos: [ の位置
+fn parse_class(chars: &mut ExprChars, pos: usize, flags: &Flags) -> Result<AST, ParseError> {
+    // 先頭の ^ は否定
+    let negated = chars.next_if(|(_, c)| *c == '^').is_some();
+
+    let mut ranges = Vec::new();
+    let mut classes = Vec::new(); // \d などで指定された文字クラス
+    loop {
+        // ] で終了
+        if chars.next_if(|(_, c)| *c == ']').is_some() {
+            break;
+        }
+
+        let (i, start) = match parse_class_item(chars, pos, flags)? {
+            (i, Escaped::Char(c)) => (i, c),
+            (_, Escaped::Class(class)) => {
+                // \d などは範囲の始点にならないので、後ろの - はただの文字として扱う
+                classes.push(class);
+                continue;
+            }
+        };
+
+        // 範囲 a-z の処理
+        // 末尾の - e.g. [a-] はただの文字として扱う
+        let is_range = chars.peek().map(|(_, c)| *c) == Some('-')
+            && chars.clone().nth(1).map(|(_, c)| c) != Some(']');
+        if is_range {
+            chars.next(); // - を読み飛ばす
+            let end = match parse_class_item(chars, pos, flags)? {
+                (_, Escaped::Char(c)) => c,
+                (j, Escaped::Class(_)) => return Err(ParseError::ClassRangeEndpoint(j)),
+            };
+            if start > end {
+                return Err(ParseError::InvalidRange(i, start, end));
+            }
+            ranges.push((start, end));
+        } else {
+            ranges.push((start, start));
+        }
+    }
+
+    if ranges.is_empty() && classes.is_empty() {
+        return Err(ParseError::EmptyClass(pos));
+    }
+
+    // 大文字・小文字の同一視は否定の前に行う
+    // e.g. (?i)[^a] は a と A のどちらにもマッチしない
+    let mut class = classes
+        .iter()
+        .fold(CharClass::new(ranges), |acc, c| acc.union(c));
+    if flags.case_insensitive {
+        class = class.case_fold();
+    }
+
+    if negated {
+        Ok(AST::Class(class.negate()))
+    } else {
+        Ok(AST::Class(class))
+    }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 enum PSQ {
     Plus,
     Star,
     Question,
 }
 
+// 量指定子の直後の ? と + を読み、量指定子の AST を生成する
+// make には貪欲かどうかを渡す
+// 直後に ? があれば非貪欲 e.g. a*? => AST::Star(AST::Char('a'), false)
+// 直後に + があれば強欲（アトミックグループ） e.g. a*+ => AST::Atomic(AST::Star(AST::Char('a'), true))
+// 拡張モードでは、間の空白とコメントを読み飛ばす e.g. a* ? => AST::Star(AST::Char('a'), false)
+fn parse_quantifier_suffix(chars: &mut ExprChars, flags: &Flags, make: impl FnOnce(bool) -> AST) -> AST {
+    if flags.extended {
+        skip_whitespace_and_comments(chars);
+    }
+
+    if chars.next_if(|(_, c)| *c == '?').is_some() {
+        make(false)
+    } else if chars.next_if(|(_, c)| *c == '+').is_some() {
+        AST::Atomic(Box::new(make(true)))
+    } else {
+        make(true)
+    }
+}
+
 fn parse_plus_star_question(
     seq: &mut Vec<AST>,
+    chars: &mut ExprChars,
     ast_type: PSQ,
     pos: usize,
+    flags: &Flags,
 ) -> Result<(), ParseError> {
     if let Some(prev) = seq.pop() {
-        let ast = match ast_type {
-            PSQ::Plus => AST::Plus(Box::new(prev)),
-            PSQ::Star => AST::Star(Box::new(prev)),
-            PSQ::Question => AST::Question(Box::new(prev)),
-        };
+        let ast = parse_quantifier_suffix(chars, flags, |greedy| match ast_type {
+            PSQ::Plus => AST::Plus(Box::new(prev), greedy),
+            PSQ::Star => AST::Star(Box::new(prev), greedy),
+            PSQ::Question => AST::Question(Box::new(prev), greedy),
+        });
         seq.push(ast);
         Ok(())
     } else {
@@ -97,6 +483,262 @@ fn parse_plus_star_question(
     }
 }
 
+// 拡張モードで無視する空白と、# から行末までのコメントを読み飛ばす
+fn skip_whitespace_and_comments(chars: &mut ExprChars) {
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_whitespace() || *c == '#') {
+        if c == '#' {
+            while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+        }
+    }
+}
+
+// 10進数の数字列を読む
+// 数字がない場合は None
+fn parse_number(chars: &mut ExprChars, pos: usize) -> Result<Option<usize>, ParseError> {
+    let mut n: Option<usize> = None;
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+        let d = c.to_digit(10).unwrap() as usize;
+        let m = n.unwrap_or(0).checked_mul(10).and_then(|m| m.checked_add(d));
+        n = Some(m.ok_or(ParseError::InvalidRepeat(pos))?); // 桁あふれはエラー
+    }
+    Ok(n)
+}
+
+// { の次の文字から } までを読み、直前の式を繰り返す AST に変換する
+// e.g. a{2,3} => AST::Repeat(AST::Char('a'), 2, Some(3), true)
+//      a{2,3}? => AST::Repeat(AST::Char('a'), 2, Some(3), false)
+// pos: { の位置
+fn parse_repeat(seq: &mut Vec<AST>, chars: &mut ExprChars, pos: usize, flags: &Flags) -> Result<(), ParseError> {
+    // 最小回数は省略できない
+    let min = parse_number(chars, pos)?.ok_or(ParseError::InvalidRepeat(pos))?;
+
+    // {n} は n 回ちょうど、{n,} は n 回以上、{n,m} は n 回以上 m 回以下
+    let max = if chars.next_if(|(_, c)| *c == ',').is_some() {
+        parse_number(chars, pos)?
+    } else {
+        Some(min)
+    };
+
+    match chars.next() {
+        Some((_, '}')) => (),
+        Some(_) => return Err(ParseError::InvalidRepeat(pos)),
+        None => return Err(ParseError::NoRightBrace(pos)),
+    }
+
+    if let Some(max) = max {
+        if min > max {
+            return Err(ParseError::InvalidRepeatRange(pos, min, max));
+        }
+    }
+
+    if let Some(prev) = seq.pop() {
+        let ast = parse_quantifier_suffix(chars, flags, |greedy| AST::Repeat(Box::new(prev), min, max, greedy));
+        seq.push(ast);
+        Ok(())
+    } else {
+        Err(ParseError::NoPrev(pos)) // e.g. 先頭に {2}
+    }
+}
+
+/// AST に含まれるキャプチャグループの数に、マッチ全体の分の 1 を足した数を返す。
+pub fn captures_len(ast: &AST) -> usize {
+    match ast {
+        AST::Capture(e, n, _) => (*n + 1).max(captures_len(e)),
+        AST::Plus(e, _)
+        | AST::Star(e, _)
+        | AST::Question(e, _)
+        | AST::Repeat(e, _, _, _)
+        | AST::LookAhead(e, _)
+        | AST::LookBehind(e, _, _, _)
+        | AST::Atomic(e) => captures_len(e),
+        AST::Or(e1, e2) => captures_len(e1).max(captures_len(e2)),
+        AST::Seq(v) => v.iter().map(captures_len).max().unwrap_or(1),
+        AST::Char(_)
+        | AST::Dot
+        | AST::Caret
+        | AST::Dollar
+        | AST::Class(_)
+        | AST::LineStart(_)
+        | AST::LineEnd(_)
+        | AST::WordBoundary(_)
+        | AST::NotWordBoundary(_)
+        | AST::BackRef(_, _) => 1,
+    }
+}
+
+/// AST に含まれる名前付きキャプチャグループの、名前から番号への対応を返す。
+pub fn capture_names(ast: &AST) -> HashMap<String, usize> {
+    fn walk(ast: &AST, names: &mut HashMap<String, usize>) {
+        match ast {
+            AST::Capture(e, n, name) => {
+                if let Some(name) = name {
+                    names.insert(name.clone(), *n);
+                }
+                walk(e, names);
+            }
+            AST::Plus(e, _)
+            | AST::Star(e, _)
+            | AST::Question(e, _)
+            | AST::Repeat(e, _, _, _)
+            | AST::LookAhead(e, _)
+            | AST::LookBehind(e, _, _, _)
+            | AST::Atomic(e) => walk(e, names),
+            AST::Or(e1, e2) => {
+                walk(e1, names);
+                walk(e2, names);
+            }
+            AST::Seq(v) => v.iter().for_each(|e| walk(e, names)),
+            AST::Char(_)
+            | AST::Dot
+            | AST::Caret
+            | AST::Dollar
+            | AST::Class(_)
+            | AST::LineStart(_)
+            | AST::LineEnd(_)
+            | AST::WordBoundary(_)
+            | AST::NotWordBoundary(_)
+            | AST::BackRef(_, _) => (),
+        }
+    }
+
+    let mut names = HashMap::new();
+    walk(ast, &mut names);
+    names
+}
+
+// AST がマッチする文字列の長さ（文字数）の最小値と最大値を返す
+// 最大値に上限がない場合は None
+// e.g. ab?c => (2, Some(3)), a+ => (1, None)
+pub fn match_len(ast: &AST) -> (usize, Option<usize>) {
+    match ast {
+        AST::Char(_) | AST::Dot | AST::Class(_) => (1, Some(1)),
+        AST::Caret
+        | AST::Dollar
+        | AST::LineStart(_)
+        | AST::LineEnd(_)
+        | AST::WordBoundary(_)
+        | AST::NotWordBoundary(_)
+        | AST::LookAhead(_, _)
+        | AST::LookBehind(_, _, _, _) => (0, Some(0)),
+        AST::Capture(e, _, _) | AST::Atomic(e) => match_len(e),
+        AST::Plus(e, _) => (match_len(e).0, None),
+        AST::Star(_, _) | AST::BackRef(_, _) => (0, None), // 後方参照の長さはマッチするまでわからない
+        AST::Question(e, _) => (0, match_len(e).1),
+        AST::Repeat(e, min, max, _) => {
+            let (e_min, e_max) = match_len(e);
+            let max = match (e_max, max) {
+                (Some(0), _) => Some(0),
+                (Some(e_max), Some(max)) => e_max.checked_mul(*max),
+                _ => None,
+            };
+            (e_min.saturating_mul(*min), max)
+        }
+        AST::Or(e1, e2) => {
+            let (min1, max1) = match_len(e1);
+            let (min2, max2) = match_len(e2);
+            (min1.min(min2), max1.zip(max2).map(|(m1, m2)| m1.max(m2)))
+        }
+        AST::Seq(v) => v.iter().map(match_len).fold((0, Some(0)), |(min, max), (e_min, e_max)| {
+            let max = max.zip(e_max).and_then(|(m, e)| m.checked_add(e));
+            (min.saturating_add(e_min), max)
+        }),
+    }
+}
+
+// \ の次の数字か k から後方参照を読み、AST に変換する
+// e.g. \12 => 12 番目のグループ、\k<name> => name という名前のグループ
+// 番号で参照するグループは後ろで定義されてもよいため、存在するかは呼び出し元で最後に確かめる
+// pos: 数字か k の位置
+fn parse_backref(
+    chars: &mut ExprChars,
+    pos: usize,
+    c: char,
+    names: &HashMap<String, usize>,
+    flags: &Flags,
+) -> Result<AST, ParseError> {
+    let n = if c == 'k' {
+        if chars.next_if(|(_, c)| *c == '<').is_none() {
+            return Err(ParseError::InvalidEscape(pos, c));
+        }
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '>')) => break,
+                Some((_, c)) => name.push(c),
+                None => return Err(ParseError::InvalidEscape(pos, 'k')),
+            }
+        }
+        *names.get(&name).ok_or(ParseError::UnknownGroupRef(pos, name))?
+    } else {
+        let mut n = c.to_digit(10).unwrap() as usize;
+        while let Some((_, d)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+            n = n
+                .checked_mul(10)
+                .and_then(|n| n.checked_add(d.to_digit(10).unwrap() as usize))
+                .ok_or_else(|| ParseError::UnknownGroupRef(pos, n.to_string()))?;
+        }
+        n
+    };
+    Ok(AST::BackRef(n, flags.case_insensitive))
+}
+
+// (? の次の P< または < から > までを読み、グループの名前を返す
+// 名前は英数字と _ からなり、数字で始まってはならない
+// pos: 開きカッコの位置
+fn parse_group_name(chars: &mut ExprChars, pos: usize) -> Result<String, ParseError> {
+    chars.next_if(|(_, c)| *c == 'P');
+    if chars.next_if(|(_, c)| *c == '<').is_none() {
+        return Err(ParseError::InvalidGroupName(pos)); // e.g. (?Pa)
+    }
+
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '>')) => break,
+            Some((_, c)) if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+            _ => return Err(ParseError::InvalidGroupName(pos)),
+        }
+    }
+
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        Err(ParseError::InvalidGroupName(pos))
+    } else {
+        Ok(name)
+    }
+}
+
+// (? の次の文字から ) か : までを読み、フラグを設定する
+// - より後ろのフラグは無効にする
+// : で終わる場合は、フラグをグループの中にのみ適用するので true を返す
+// e.g. (?m) => マルチラインモードを有効にする
+//      (?i-m) => 大文字・小文字を同一視し、マルチラインモードを無効にする
+//      (?i:abc) => abc の中でのみ大文字・小文字を同一視する
+fn parse_flags(chars: &mut ExprChars, flags: &mut Flags) -> Result<bool, ParseError> {
+    let mut enable = true; // - の前なら有効に、後ろなら無効にする
+    let mut empty = true; // 直前の ( か - の後にフラグがない
+    loop {
+        let (i, c) = chars.next().ok_or(ParseError::NoRightParen)?;
+        match c {
+            ')' | ':' if empty => return Err(ParseError::InvalidFlags(i)), // e.g. (?), (?i-)
+            ')' | ':' => return Ok(c == ':'),
+            '-' if !enable => return Err(ParseError::InvalidFlags(i)), // e.g. (?i-m-s)
+            '-' => {
+                enable = false;
+                empty = true;
+                continue;
+            }
+            'i' => flags.case_insensitive = enable,
+            'm' => flags.multi_line = enable,
+            's' => flags.dot_all = enable,
+            'x' => flags.extended = enable,
+            'u' => flags.unicode = enable,
+            'R' => flags.crlf = enable,
+            _ => return Err(ParseError::UnknownFlag(i, c)),
+        }
+        empty = false;
+    }
+}
+
 // Or で結合された複数式を AST に変換する
 // e.g. abc | def | ghi => AST::Or("abc", AST::Or("def", "ghi"))
 fn fold_or(mut seq_or: Vec<AST>) -> Option<AST> {
@@ -112,7 +754,9 @@ fn fold_or(mut seq_or: Vec<AST>) -> Option<AST> {
     }
 }
 
-pub fn parse(expr: &str) -> Result<AST, ParseError> {
+/// 正規表現の式をパースし、AST に変換する。
+/// flags はパース開始時のフラグ。
+pub fn parse(expr: &str, flags: Flags) -> Result<AST, ParseError> {
     // 内部状態を表現するための型
     // Char 状態: 文字列処理中
     // Escape 状態: エスケープシーケンス処理中
@@ -121,40 +765,116 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
         Escape,
     }
 
+    // 開いているグループの種類
+    enum Group {
+        Capture(usize, Option<String>), // キャプチャグループ（番号, 名前）
+        NonCapture,                     // (?:...)
+        Atomic,                         // (?>...)
+        LookAhead(bool),                // (?=...) と (?!...)（否定かどうか）
+        LookBehind(bool, usize),        // (?<=...) と (?<!...)（否定かどうか, 開きカッコの位置）
+    }
+
     let mut seq = Vec::new(); // 現在の seq コンテキスト e.g. "abc"
     let mut seq_or = Vec::new(); // 現在の Or コンテキスト(本体) e.g. "abc|de"
     let mut stack = Vec::new(); // コンテキストのスタック(一次保存)
     let mut state = ParseState::Char;  // 現在の状態
+    let mut group = 0; // 最後に開いたキャプチャグループの番号
+    let mut names = HashMap::new(); // 名前付きグループの名前と番号
+    let mut backrefs = Vec::new(); // 番号による後方参照（位置, 番号）
+    let mut flags = flags; // 現在のフラグ
 
-    for (i, c) in expr.chars().enumerate() {
+    let mut chars = expr.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
         match &state {
             ParseState::Char => {
+                // 拡張モードでは、エスケープされていない空白と # から行末までのコメントを無視する
+                // 文字クラスの中の空白と # は無視しない
+                if flags.extended {
+                    if c.is_whitespace() {
+                        continue;
+                    }
+                    if c == '#' {
+                        while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+                        continue;
+                    }
+                }
+
                 match c {
-                    '+' => parse_plus_star_question(&mut seq, PSQ::Plus, i)?,  // seq につめる, pos はエラー用 e.g AST::Plus(Box::new(seq)),
-                    '*' => parse_plus_star_question(&mut seq, PSQ::Star, i)?,
-                    '?' => parse_plus_star_question(&mut seq, PSQ::Question, i)?,
+                    '+' => parse_plus_star_question(&mut seq, &mut chars, PSQ::Plus, i, &flags)?,  // seq につめる, pos はエラー用 e.g AST::Plus(Box::new(seq)),
+                    '*' => parse_plus_star_question(&mut seq, &mut chars, PSQ::Star, i, &flags)?,
+                    '?' => parse_plus_star_question(&mut seq, &mut chars, PSQ::Question, i, &flags)?,
+                    '{' => parse_repeat(&mut seq, &mut chars, i, &flags)?,
                     '(' => {
+                        // (?: は非キャプチャグループ、(?> はアトミックグループ、(?= と (?! は先読み、(?<= と (?<! は後読み、
+                        // (?P<name> と (?<name> は名前付きグループ、それ以外の (? はフラグの変更 e.g. (?i) や (?i:...)
+                        let mut group_flags = flags; // グループの中に適用するフラグ
+                        let kind = if chars.next_if(|(_, c)| *c == '?').is_none() {
+                            group += 1;
+                            Group::Capture(group, None)
+                        } else if chars.next_if(|(_, c)| *c == ':').is_some() {
+                            Group::NonCapture
+                        } else if chars.next_if(|(_, c)| *c == '>').is_some() {
+                            Group::Atomic
+                        } else if let Some((_, c)) = chars.next_if(|(_, c)| *c == '=' || *c == '!') {
+                            Group::LookAhead(c == '!')
+                        } else if chars.peek().map(|(_, c)| *c) == Some('<')
+                            && matches!(chars.clone().nth(1), Some((_, '=' | '!')))
+                        {
+                            chars.next(); // < を読み飛ばす
+                            let (_, c) = chars.next().unwrap();
+                            Group::LookBehind(c == '!', i)
+                        } else if matches!(chars.peek(), Some((_, 'P' | '<'))) {
+                            let name = parse_group_name(&mut chars, i)?;
+                            group += 1;
+                            if names.insert(name.clone(), group).is_some() {
+                                return Err(ParseError::DuplicateGroupName(i, name));
+                            }
+                            Group::Capture(group, Some(name))
+                        } else if parse_flags(&mut chars, &mut group_flags)? {
+                            // (?m:...) のフラグの変更は、グループの中にのみ適用する
+                            Group::NonCapture
+                        } else {
+                            // (?m) などのフラグの変更は、グループの残りの部分に適用する
+                            flags = group_flags;
+                            continue;
+                        };
+
                         // 現在のコンテキストをスタックに保存し、
                         // 現在のコンテキストを空の状態にする
+                        // キャプチャグループの番号は開きカッコの順に振る
                         let prev = take(&mut seq);
                         let prev_or = take(&mut seq_or);
-                        stack.push((prev, prev_or));
+                        stack.push((prev, prev_or, kind, flags));
+                        flags = group_flags;
                     }
                     ')' => {
                         // 現在のコンテキストをスタックからポップ
-                        if let Some((mut prev, prev_or)) = stack.pop() {
+                        if let Some((mut prev, prev_or, kind, prev_flags)) = stack.pop() {
                             // "()" のように、式が殻の場合は push しない "(abc|de|)"とかもかな..なんでエラーちゃうんやろ？再利用用？
                             if !seq.is_empty() {
                                 seq_or.push(AST::Seq(seq))
                             }
 
                             // Or を生成 e.g. AST::Or("abc", AST::Or("def", "ghi"))
-                            if let Some(ast) = fold_or(seq_or) {
-                                prev.push(ast);
+                            // "()" のように式が空の場合も、空文字列をキャプチャする
+                            let ast = fold_or(seq_or).unwrap_or(AST::Seq(Vec::new()));
+                            match kind {
+                                Group::Capture(n, name) => prev.push(AST::Capture(Box::new(ast), n, name)),
+                                Group::NonCapture => prev.push(ast),
+                                Group::Atomic => prev.push(AST::Atomic(Box::new(ast))),
+                                Group::LookAhead(negated) => prev.push(AST::LookAhead(Box::new(ast), negated)),
+                                Group::LookBehind(negated, pos) => {
+                                    // 後読みは開始位置を戻して評価するため、長さに上限が必要
+                                    let (min, max) = match_len(&ast);
+                                    let max = max.ok_or(ParseError::UnboundedLookBehind(pos))?;
+                                    prev.push(AST::LookBehind(Box::new(ast), negated, min, max));
+                                }
                             }
 
                             // 以前のコンテキストを 現在のコンテキストにする
+                            // グループ内で変更したフラグも元に戻す
                             seq = prev;
+                            flags = prev_flags;
                             seq_or = prev_or; // ??これが残っていることある？ abc|(ed)とかはそうなりそう
                         } else {
                             // "abc)" のように開きカッコがない場合はエラー
@@ -173,15 +893,27 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
                         }
                     }
                     '\\' => state = ParseState::Escape,
-                    '.' => seq.push(AST::Dot),
+                    '.' => seq.push(parse_dot(&flags)),
+                    '^' if flags.multi_line => seq.push(AST::LineStart(flags.crlf)),
+                    '$' if flags.multi_line => seq.push(AST::LineEnd(flags.crlf)),
                     '^' => seq.push(AST::Caret),
                     '$' => seq.push(AST::Dollar),
-                    _ => seq.push(AST::Char(c)),
+                    '[' => seq.push(parse_class(&mut chars, i, &flags)?),
+                    _ => seq.push(parse_char(c, &flags)),
                 }
             }
             ParseState::Escape => {
                 // エスケープシーケンス処理
-                let ast = parse_escape(i, c)?;
+                let ast = match c {
+                    '1'..='9' | 'k' => {
+                        let ast = parse_backref(&mut chars, i, c, &names, &flags)?;
+                        if let AST::BackRef(n, _) = ast {
+                            backrefs.push((i, n));
+                        }
+                        ast
+                    }
+                    _ => parse_escape(&mut chars, i, c, &flags)?,
+                };
                 seq.push(ast);
                 state = ParseState::Char;
             }
@@ -195,6 +927,11 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
         return Err(ParseError::NoRightParen);
     }
 
+    // 存在しないグループへの後方参照はエラー
+    if let Some((pos, n)) = backrefs.into_iter().find(|(_, n)| *n > group) {
+        return Err(ParseError::UnknownGroupRef(pos, n.to_string()));
+    }
+
     // "()" のように、式が空の場合は push しない
     // 最後の文字列はここでpushされる
     if !seq.is_empty() {
@@ -0,0 +1,662 @@
+//! コンパイル済みの正規表現
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+use std::sync::Arc;
+use crate::engine::{codegen, evaluator, parser, Instruction};
+use crate::engine::captures::Captures;
+use crate::engine::replacer::Replacer;
+use crate::helpers::DynError;
+
+/// コンパイル済みの正規表現
+///
+/// パースとコード生成は [`Regex::new`] で一度だけ行い、
+/// 生成した命令列を使い回してマッチングを行う。
+/// 内部状態を持たないため、スレッド間で共有して利用できる。
+///
+/// # 利用例
+///
+/// ```
+/// use regex::Regex;
+/// let re = Regex::new("abc|(de|cd)+").unwrap();
+/// assert!(re.is_match("xxdecd"));
+/// assert!(!re.is_match("xxdx"));
+/// ```
+#[derive(Debug)]
+pub struct Regex {
+    expr: String,            // 元の正規表現
+    code: Vec<Instruction>,  // 命令列
+    captures_len: usize,     // キャプチャグループの数（マッチ全体を含む）
+    names: Arc<HashMap<String, usize>>, // 名前付きグループの名前と番号
+    has_backref: bool,       // 後方参照を含むかどうか
+    is_depth: bool,          // 深さ優先探索で評価するかどうか
+}
+
+impl Regex {
+    /// 正規表現をパースしてコード生成し、コンパイル済みの正規表現を返す。
+    /// オプションを指定する場合は [`RegexBuilder`] を使う。
+    ///
+    /// # 返り値
+    ///
+    /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
+    pub fn new(expr: &str) -> Result<Regex, DynError> {
+        RegexBuilder::new(expr).build()
+    }
+
+    /// コンパイル元の正規表現を返す。
+    pub fn as_str(&self) -> &str {
+        &self.expr
+    }
+
+    /// キャプチャグループの数を返す。マッチ全体を表す 0 番目のグループも数える。
+    pub fn captures_len(&self) -> usize {
+        self.captures_len
+    }
+
+    /// 名前付きキャプチャグループの、名前から番号への対応を返す。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// let re = Regex::new("(?P<key>[a-z]+)=(?:[0-9]+)(?P<unit>[a-z]*)").unwrap();
+    /// assert_eq!(re.capture_names().get("key"), Some(&1));
+    /// assert_eq!(re.capture_names().get("unit"), Some(&2));
+    /// assert_eq!(re.captures_len(), 3);
+    /// ```
+    pub fn capture_names(&self) -> &HashMap<String, usize> {
+        &self.names
+    }
+
+    /// line のいずれかの位置から正規表現にマッチするかを返す。
+    pub fn is_match(&self, line: &str) -> bool {
+        let input = Input::new(line);
+        self.search(&input, 0, &mut [])
+    }
+
+    /// line の中で最初に（最も左で）マッチした箇所を返す。
+    /// マッチしない場合は None。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// let re = Regex::new("[0-9]+").unwrap();
+    /// let m = re.find("値: 42 と 7").unwrap();
+    /// assert_eq!(m.as_str(), "42");
+    /// assert_eq!(m.range(), 5..7); // 位置はバイト単位
+    /// ```
+    pub fn find<'t>(&self, line: &'t str) -> Option<Match<'t>> {
+        self.find_at(line, 0)
+    }
+
+    /// line の start バイト目以降で、最初にマッチした箇所を返す。
+    /// マッチしない場合は None。
+    ///
+    /// start より前の文字列も ^ などの判定には使われるため、
+    /// `find(&line[start..])` とは結果が異なる場合がある。
+    ///
+    /// # パニック
+    ///
+    /// start が文字の境界でない場合はパニックする。
+    pub fn find_at<'t>(&self, line: &'t str, start: usize) -> Option<Match<'t>> {
+        let input = Input::new(line);
+        let mut slots = [None, None];
+        if self.search(&input, input.char_pos(start), &mut slots) {
+            Some(input.get_match(&slots))
+        } else {
+            None
+        }
+    }
+
+    /// line の中で最初にマッチした箇所について、各キャプチャグループの位置を返す。
+    /// マッチしない場合は None。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// let re = Regex::new("([a-z]+)=([0-9]+)").unwrap();
+    /// let caps = re.captures("id: key=42").unwrap();
+    /// assert_eq!(&caps[0], "key=42");
+    /// assert_eq!(&caps[1], "key");
+    /// assert_eq!(caps.get(2).unwrap().range(), 8..10);
+    /// ```
+    pub fn captures<'t>(&self, line: &'t str) -> Option<Captures<'t>> {
+        self.captures_at(line, 0)
+    }
+
+    /// line の start バイト目以降で最初にマッチした箇所について、各キャプチャグループの位置を返す。
+    /// マッチしない場合は None。
+    ///
+    /// # パニック
+    ///
+    /// start が文字の境界でない場合はパニックする。
+    pub fn captures_at<'t>(&self, line: &'t str, start: usize) -> Option<Captures<'t>> {
+        let input = Input::new(line);
+        let mut slots = vec![None; self.captures_len * 2];
+        if self.search(&input, input.char_pos(start), &mut slots) {
+            Some(input.get_captures(&slots, &self.names))
+        } else {
+            None
+        }
+    }
+
+    /// line の中で重ならずにマッチするすべての箇所を、先頭から順に返すイテレータを返す。
+    ///
+    /// 空文字列にマッチした場合は、次の探索を 1 文字進めてから行う。
+    /// また、直前のマッチの終了位置と同じ位置で空文字列にマッチした場合は、その箇所は返さない。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// let re = Regex::new("[0-9]+").unwrap();
+    /// let nums = re.find_iter("1, 22, 333").map(|m| m.as_str()).collect::<Vec<_>>();
+    /// assert_eq!(nums, vec!["1", "22", "333"]);
+    ///
+    /// // "aaa" の直後の空文字列は返さない
+    /// let re = Regex::new("a*").unwrap();
+    /// let ranges = re.find_iter("baaa").map(|m| m.range()).collect::<Vec<_>>();
+    /// assert_eq!(ranges, vec![0..0, 1..4]);
+    /// ```
+    pub fn find_iter<'r, 't>(&'r self, line: &'t str) -> Matches<'r, 't> {
+        Matches(MatchIter::new(self, line))
+    }
+
+    /// line の中で重ならずにマッチするすべての箇所について、各キャプチャグループの位置を返すイテレータを返す。
+    /// 空文字列へのマッチの扱いは [`Regex::find_iter`] と同じ。
+    pub fn captures_iter<'r, 't>(&'r self, line: &'t str) -> CaptureMatches<'r, 't> {
+        CaptureMatches(MatchIter::new(self, line))
+    }
+
+    /// line の中で最初にマッチした箇所を rep で置換した文字列を返す。
+    /// マッチしない場合は、line をコピーせずにそのまま返す。
+    ///
+    /// rep にはテンプレート文字列（`$1` や `${name}` などを展開する）か、
+    /// キャプチャを受け取って置換後の文字列を返すクロージャを渡す（[`Replacer`] を参照）。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// let re = Regex::new("([0-9]+)-([0-9]+)").unwrap();
+    /// assert_eq!(re.replace("1-2, 3-4", "$2-$1"), "2-1, 3-4");
+    /// ```
+    pub fn replace<'t, R: Replacer>(&self, line: &'t str, rep: R) -> Cow<'t, str> {
+        self.replacen(line, 1, rep)
+    }
+
+    /// line の中で重ならずにマッチするすべての箇所を rep で置換した文字列を返す。
+    /// マッチしない場合は、line をコピーせずにそのまま返す。
+    pub fn replace_all<'t, R: Replacer>(&self, line: &'t str, rep: R) -> Cow<'t, str> {
+        self.replacen(line, 0, rep)
+    }
+
+    /// line の中で先頭から limit 個までのマッチした箇所を rep で置換した文字列を返す。
+    /// limit が 0 の場合はすべての箇所を置換する。
+    /// マッチしない場合は、line をコピーせずにそのまま返す。
+    pub fn replacen<'t, R: Replacer>(&self, line: &'t str, limit: usize, mut rep: R) -> Cow<'t, str> {
+        let mut new = String::with_capacity(line.len());
+        let mut last = 0; // 置換していない部分の開始位置
+        let mut is_replaced = false;
+
+        for (i, caps) in self.captures_iter(line).enumerate() {
+            if limit > 0 && i >= limit {
+                break;
+            }
+
+            let m = caps.get(0).unwrap();
+            new.push_str(&line[last..m.start()]);
+            rep.replace_append(&caps, &mut new);
+            last = m.end();
+            is_replaced = true;
+        }
+
+        if is_replaced {
+            new.push_str(&line[last..]);
+            Cow::Owned(new)
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+
+    /// line をマッチした箇所で区切った部分文字列を、先頭から順に返すイテレータを返す。
+    /// マッチした箇所は [`Regex::find_iter`] と同じ規則で探索する。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// let re = Regex::new("[ \t]*,[ \t]*").unwrap();
+    /// let fields = re.split("a, b,c ,,d").collect::<Vec<_>>();
+    /// assert_eq!(fields, vec!["a", "b", "c", "", "d"]);
+    /// ```
+    pub fn split<'r, 't>(&'r self, line: &'t str) -> Split<'r, 't> {
+        Split {
+            finder: self.find_iter(line),
+            line,
+            last: Some(0),
+        }
+    }
+
+    /// line をマッチした箇所で区切った部分文字列を、先頭から最大 limit 個まで返すイテレータを返す。
+    /// 最後の部分文字列には、区切られなかった残りの文字列全体が入る。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// let re = Regex::new("=").unwrap();
+    /// let kv = re.splitn("key=a=b", 2).collect::<Vec<_>>();
+    /// assert_eq!(kv, vec!["key", "a=b"]);
+    /// ```
+    pub fn splitn<'r, 't>(&'r self, line: &'t str, limit: usize) -> SplitN<'r, 't> {
+        SplitN {
+            splits: self.split(line),
+            limit,
+        }
+    }
+
+    // input の start 文字目以降で最初にマッチする箇所を探索する
+    // 命令列はコンパイル時に検査済みなので、評価時のエラーは評価器の実装誤り
+    fn search(&self, input: &Input, start: usize, slots: &mut [Option<usize>]) -> bool {
+        // 後方参照はキャプチャの位置を使うため、呼び出し元が位置を必要としない場合も記録する
+        if self.has_backref && slots.len() < self.captures_len * 2 {
+            let mut all = vec![None; self.captures_len * 2];
+            let is_match = self.search(input, start, &mut all);
+            let len = slots.len();
+            slots.copy_from_slice(&all[..len]);
+            return is_match;
+        }
+
+        match evaluator::search(&self.code, &input.chars, start, self.is_depth, slots) {
+            Ok(is_match) => is_match,
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
+
+/// マッチした箇所
+///
+/// 位置は元の文字列のバイト単位で表す。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'t> {
+    line: &'t str,
+    start: usize,
+    end: usize,
+}
+
+impl<'t> Match<'t> {
+    pub(crate) fn new(line: &'t str, start: usize, end: usize) -> Match<'t> {
+        Match { line, start, end }
+    }
+
+    /// マッチした箇所の開始位置を返す。
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// マッチした箇所の終了位置を返す。
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// マッチした箇所の範囲を返す。
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// マッチした部分文字列を返す。
+    pub fn as_str(&self) -> &'t str {
+        &self.line[self.range()]
+    }
+}
+
+/// [`Regex::find_iter`] が返す、マッチした箇所のイテレータ
+#[derive(Debug)]
+pub struct Matches<'r, 't>(MatchIter<'r, 't>);
+
+impl<'t> Iterator for Matches<'_, 't> {
+    type Item = Match<'t>;
+
+    fn next(&mut self) -> Option<Match<'t>> {
+        let mut slots = [None, None];
+        if self.0.next_slots(&mut slots) {
+            Some(self.0.input.get_match(&slots))
+        } else {
+            None
+        }
+    }
+}
+
+/// [`Regex::captures_iter`] が返す、マッチした箇所のキャプチャのイテレータ
+#[derive(Debug)]
+pub struct CaptureMatches<'r, 't>(MatchIter<'r, 't>);
+
+impl<'t> Iterator for CaptureMatches<'_, 't> {
+    type Item = Captures<'t>;
+
+    fn next(&mut self) -> Option<Captures<'t>> {
+        let mut slots = vec![None; self.0.re.captures_len * 2];
+        if self.0.next_slots(&mut slots) {
+            Some(self.0.input.get_captures(&slots, &self.0.re.names))
+        } else {
+            None
+        }
+    }
+}
+
+/// [`Regex::split`] が返す、区切った部分文字列のイテレータ
+#[derive(Debug)]
+pub struct Split<'r, 't> {
+    finder: Matches<'r, 't>,
+    line: &'t str,
+    last: Option<usize>, // 次の部分文字列の開始位置。すべて返した場合は None
+}
+
+impl<'t> Iterator for Split<'_, 't> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<&'t str> {
+        let last = self.last?;
+        match self.finder.next() {
+            Some(m) => {
+                self.last = Some(m.end());
+                Some(&self.line[last..m.start()])
+            }
+            None => {
+                // 最後のマッチ以降の残り
+                self.last = None;
+                Some(&self.line[last..])
+            }
+        }
+    }
+}
+
+/// [`Regex::splitn`] が返す、区切った部分文字列のイテレータ
+#[derive(Debug)]
+pub struct SplitN<'r, 't> {
+    splits: Split<'r, 't>,
+    limit: usize, // 残りの部分文字列の数
+}
+
+impl<'t> Iterator for SplitN<'_, 't> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<&'t str> {
+        match self.limit {
+            0 => None,
+            1 => {
+                // 最後は区切らずに残り全体を返す
+                self.limit = 0;
+                let last = self.splits.last.take()?;
+                Some(&self.splits.line[last..])
+            }
+            _ => {
+                self.limit -= 1;
+                self.splits.next()
+            }
+        }
+    }
+}
+
+// 重ならずにマッチする箇所を先頭から順に探索する
+#[derive(Debug)]
+struct MatchIter<'r, 't> {
+    re: &'r Regex,
+    input: Input<'t>,
+    last_end: usize,           // 次の探索の開始位置（文字単位）
+    last_match: Option<usize>, // 直前のマッチの終了位置（文字単位）
+}
+
+impl<'r, 't> MatchIter<'r, 't> {
+    fn new(re: &'r Regex, line: &'t str) -> MatchIter<'r, 't> {
+        MatchIter {
+            re,
+            input: Input::new(line),
+            last_end: 0,
+            last_match: None,
+        }
+    }
+
+    // 次にマッチする箇所を探索し、位置を slots に設定する
+    // マッチする箇所がもうない場合は false
+    fn next_slots(&mut self, slots: &mut [Option<usize>]) -> bool {
+        loop {
+            if self.last_end > self.input.chars.len()
+                || !self.re.search(&self.input, self.last_end, slots)
+            {
+                return false;
+            }
+
+            let end = if let Some(end) = slots[1] {
+                end
+            } else {
+                return false;
+            };
+
+            if slots[0] == Some(end) {
+                // 空文字列にマッチした場合は、同じ位置で無限にマッチし続けないよう
+                // 次の探索を 1 文字進める
+                self.last_end = end + 1;
+
+                // 直前のマッチの直後の空文字列は読み飛ばす
+                // e.g. "baaa" に対する a* の 1..4 の直後の 4..4
+                if self.last_match == Some(end) {
+                    continue;
+                }
+            } else {
+                self.last_end = end;
+            }
+
+            self.last_match = Some(end);
+            return true;
+        }
+    }
+}
+
+// マッチング対象の文字列
+// 評価器は文字単位で位置を扱うため、文字の列と各文字のバイト位置を保持しておく
+#[derive(Debug)]
+struct Input<'t> {
+    line: &'t str,
+    chars: Vec<char>,
+    offsets: Vec<usize>, // i 文字目のバイト位置。末尾には文字列のバイト長を入れる
+}
+
+impl<'t> Input<'t> {
+    fn new(line: &'t str) -> Input<'t> {
+        let (mut offsets, chars): (Vec<usize>, Vec<char>) = line.char_indices().unzip();
+        offsets.push(line.len());
+        Input { line, chars, offsets }
+    }
+
+    // バイト位置を文字の位置に変換する
+    fn char_pos(&self, byte: usize) -> usize {
+        self.offsets
+            .binary_search(&byte)
+            .unwrap_or_else(|_| panic!("byte index {byte} is not a char boundary"))
+    }
+
+    // 評価器が設定したマッチ全体の位置（文字単位）から、マッチした箇所を生成する
+    fn get_match(&self, slots: &[Option<usize>]) -> Match<'t> {
+        match (slots[0], slots[1]) {
+            (Some(start), Some(end)) => Match::new(self.line, self.offsets[start], self.offsets[end]),
+            _ => panic!("no match position"),
+        }
+    }
+
+    // 評価器が設定したキャプチャの位置（文字単位）を、バイト単位に変換する
+    fn get_captures(&self, slots: &[Option<usize>], names: &Arc<HashMap<String, usize>>) -> Captures<'t> {
+        let slots = slots.iter().map(|s| s.map(|i| self.offsets[i])).collect();
+        Captures::new(self.line, slots, Arc::clone(names))
+    }
+}
+
+/// オプションを指定して正規表現をコンパイルするためのビルダ
+///
+/// # 利用例
+///
+/// ```
+/// use regex::RegexBuilder;
+/// let re = RegexBuilder::new("a{2,3}")
+///     .size_limit(1000)
+///     .depth_first(true)
+///     .build()
+///     .unwrap();
+/// assert!(re.is_match("baab"));
+///
+/// // 展開後の命令数が上限を超える場合はエラー
+/// assert!(RegexBuilder::new("a{1000}{1000}").build().is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    expr: String,
+    flags: parser::Flags,
+    size_limit: usize,
+    is_depth: Option<bool>, // None の場合は正規表現に応じて自動で選ぶ
+}
+
+impl RegexBuilder {
+    /// デフォルトのオプションでビルダを生成する。
+    pub fn new(expr: &str) -> RegexBuilder {
+        RegexBuilder {
+            expr: expr.to_string(),
+            flags: parser::Flags::default(),
+            size_limit: codegen::DEFAULT_SIZE_LIMIT,
+            is_depth: None,
+        }
+    }
+
+    /// マルチラインモードを設定する。
+    /// 有効な場合、^ と $ は文字列の先頭と末尾に加えて、各行の先頭と末尾にもマッチする。
+    /// 正規表現中で (?m) と書いた場合も有効になる。
+    ///
+    /// フラグによらず文字列の先頭と末尾にのみマッチさせる場合は、\A と \z を使う。
+    pub fn multi_line(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    /// 大文字・小文字を同一視するかどうかを設定する。
+    /// 正規表現中で (?i) と書いた場合も有効になる。
+    ///
+    /// 文字列を小文字に変換して比較するのではなく、正規表現中の文字と文字クラスを
+    /// Unicode の単純大文字小文字変換で同一視される文字を含むように変換する。
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.case_insensitive = yes;
+        self
+    }
+
+    /// \d, \w, \s を Unicode の定義にするかどうかを設定する（デフォルトは true）。
+    ///
+    /// false の場合は ASCII の範囲のみ e.g. \d は [0-9]、\w は [0-9A-Za-z_] にマッチする。
+    pub fn unicode(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.unicode = yes;
+        self
+    }
+
+    /// ドットオールモードを設定する。
+    /// 正規表現中で (?s) と書いた場合も有効になる。
+    ///
+    /// デフォルトでは . は改行以外の 1 文字にマッチし、有効な場合は改行を含む任意の 1 文字にマッチする。
+    pub fn dot_all(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.dot_all = yes;
+        self
+    }
+
+    /// 行の区切りに \r\n を含めるかどうかを設定する。
+    /// 正規表現中で (?R) と書いた場合も有効になる。
+    ///
+    /// 有効な場合、. は \n に加えて \r にもマッチしなくなり、マルチラインモードの ^ と $ は
+    /// \r\n の前後にマッチする（\r と \n の間にはマッチしない）。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::RegexBuilder;
+    /// let re = RegexBuilder::new("^.+$").multi_line(true).crlf(true).build().unwrap();
+    /// let lines = re.find_iter("a\r\nbc\r\n").map(|m| m.as_str()).collect::<Vec<_>>();
+    /// assert_eq!(lines, vec!["a", "bc"]);
+    /// ```
+    pub fn crlf(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.crlf = yes;
+        self
+    }
+
+    /// 拡張モードを設定する。
+    /// 正規表現中で (?x) と書いた場合も有効になる。
+    ///
+    /// 有効な場合、エスケープされていない空白と、# から行末までのコメントを無視する。
+    /// 空白と # そのものには \  と \# でマッチさせる。文字クラスの中の空白と # は無視しない。
+    /// 量指定子と、直後の非貪欲を表す ? や強欲を表す + の間の空白とコメントも無視するため、
+    /// `a + ?` は `(a+)?` ではなく非貪欲な `a+?` となる。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex::RegexBuilder;
+    /// let re = RegexBuilder::new(r"
+    ///     (?P<year>\d{4}) - (?P<month>\d{2})  # 年と月
+    ///     \ (?P<day>\d{2})                    # 空白の後に日
+    /// ").extended(true).build().unwrap();
+    /// let caps = re.captures("2024-05 17").unwrap();
+    /// assert_eq!(&caps["day"], "17");
+    /// ```
+    pub fn extended(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.extended = yes;
+        self
+    }
+
+    /// 生成する命令数の上限を設定する。
+    /// {n,m} の展開などで命令数が上限を超える場合、コンパイルはエラーになる。
+    pub fn size_limit(&mut self, limit: usize) -> &mut RegexBuilder {
+        self.size_limit = limit;
+        self
+    }
+
+    /// 深さ優先探索で評価するかどうかを設定する。
+    ///
+    /// 設定しない場合は幅優先探索で評価し、幅優先探索で評価できない先読み・後読みや
+    /// 後方参照を含む場合のみ深さ優先探索で評価する。
+    /// 幅優先探索を指定した正規表現がこれらを含む場合、コンパイルはエラーになる。
+    pub fn depth_first(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.is_depth = Some(yes);
+        self
+    }
+
+    /// 正規表現をパースしてコード生成し、コンパイル済みの正規表現を返す。
+    ///
+    /// # 返り値
+    ///
+    /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
+    pub fn build(&self) -> Result<Regex, DynError> {
+        let ast = parser::parse(&self.expr, self.flags)?; // AST変換
+        let code = codegen::get_code(&ast, self.size_limit)?; // 命令に変換
+        let has_backref = code.iter().any(|i| matches!(i, Instruction::BackRef(..)));
+        let is_depth = match self.is_depth {
+            Some(true) => true,
+            Some(false) => {
+                evaluator::check_width(&code)?;
+                false
+            }
+            None => evaluator::check_width(&code).is_err(),
+        };
+        Ok(Regex {
+            expr: self.expr.clone(),
+            code,
+            captures_len: parser::captures_len(&ast),
+            names: Arc::new(parser::capture_names(&ast)),
+            has_backref,
+            is_depth,
+        })
+    }
+}
+
+impl Display for Regex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
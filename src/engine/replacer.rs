@@ -0,0 +1,53 @@
+//! 置換後の文字列の生成
+use crate::engine::captures::Captures;
+
+/// マッチした箇所の置換後の文字列を生成するトレイト
+///
+/// 文字列は `$1` や `${name}` などのテンプレートとして展開し（[`Captures::expand`] を参照）、
+/// クロージャはキャプチャを受け取って置換後の文字列を返す。
+///
+/// # 利用例
+///
+/// ```
+/// use regex::{Captures, Regex};
+/// let re = Regex::new("([a-z]+)=([0-9]+)").unwrap();
+/// assert_eq!(re.replace_all("a=1 b=2", "$2=$1"), "1=a 2=b");
+///
+/// let doubled = re.replace_all("a=1 b=2", |caps: &Captures| {
+///     let n = caps[2].parse::<u32>().unwrap();
+///     format!("{}={}", &caps[1], n * 2)
+/// });
+/// assert_eq!(doubled, "a=2 b=4");
+/// ```
+pub trait Replacer {
+    /// マッチした箇所の置換後の文字列を dst の末尾に追加する。
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String);
+}
+
+impl Replacer for &str {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst);
+    }
+}
+
+impl Replacer for String {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst);
+    }
+}
+
+impl Replacer for &String {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst);
+    }
+}
+
+impl<F, T> Replacer for F
+where
+    F: FnMut(&Captures<'_>) -> T,
+    T: AsRef<str>,
+{
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        dst.push_str((*self)(caps).as_ref());
+    }
+}
@@ -0,0 +1,6 @@
+//! Unicode の表
+//!
+//! 各ファイルは scripts/gen_unicode_tables.pl で生成する。
+pub mod case_folding;
+pub mod perl;
+pub mod property;
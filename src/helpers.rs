@@ -21,4 +21,29 @@ pub fn safe_add<T, F, E>(dst: &mut T, src: &T, f: F) -> Result<(), E>
     }
 }
 
-pub type DynError = Box<dyn std::error::Error + Send + Sync + 'static>;
\ No newline at end of file
+pub type DynError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[cfg(test)]
+mod tests {
+    use super::{safe_add, SafeAdd};
+
+    #[test]
+    fn test_safe_add() {
+        let u = 10;
+        assert_eq!(Some(30), u.safe_add(&20));
+
+        let u = !0; // 2^64 -1 (64bit cpuの場合) 18446744073709551615
+        assert_eq!(None, u.safe_add(&1));
+
+        let u = 18446744073709551614; // ギリギリ足せる
+        assert_eq!(Some(18446744073709551615), u.safe_add(&1));
+
+        // エラーのクロージャ渡してもOK
+        let mut n = 10;
+        assert!(safe_add(&mut n, &20, || ()).is_ok());
+
+        // エラーの確認
+        let mut n = !0;
+        assert!(safe_add(&mut n, &1, || ()).is_err());
+    }
+}
\ No newline at end of file
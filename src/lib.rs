@@ -6,10 +6,18 @@
 //! use regex;
 //! let expr = "a(bc)+|c(def)*";
 //! let line = "cdefdefdef";
-//! regex::do_matching(expr, line, true);
+//! regex::do_matching(expr, line, 0, true);
 //! regex::print(expr);
+//!
+//! // 同じ正規表現を繰り返し使う場合は、一度だけコンパイルする
+//! let re = regex::Regex::new(expr).unwrap();
+//! assert!(re.is_match(line));
 //! ```
 mod engine;
 mod helpers;
 
-pub use engine::{do_matching, print};
\ No newline at end of file
+pub use engine::{
+    do_matching, print, CaptureMatches, Captures, Match, Matches, Regex, RegexBuilder, Replacer,
+    Split, SplitN,
+};
+pub use helpers::DynError;
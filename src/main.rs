@@ -1,66 +1,74 @@
 use std::env;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use crate::helpers::DynError;
-
-mod engine;
-mod helpers;
+use regex::{DynError, Regex};
 
 // cargo run "abc*" regex.tex
+// cargo run -- -o "abc*" regex.tex （マッチした部分のみを表示）
 fn main() -> Result<(), DynError> {
     let args: Vec<String> = env::args().collect();
-    if args.len() <= 2 {
-        eprintln!("usage: {} regex file", args[0]); // 標準エラー出力の eprintln!
+    let (only_matching, rest) = if args.get(1).is_some_and(|a| a == "-o") {
+        (true, &args[2..])
+    } else {
+        (false, &args[1..])
+    };
+
+    if rest.len() < 2 {
+        eprintln!("usage: {} [-o] regex file", args[0]); // 標準エラー出力の eprintln!
         return Err("Invalid arguments".into());
     } else {
-        match_file(&args[1], &args[2])?;
+        match_file(&rest[0], &rest[1], only_matching)?;
     }
     Ok(())
 }
 
 
-fn match_file(expr: &str, file: &str)-> Result<(), DynError> {
+fn match_file(expr: &str, file: &str, only_matching: bool)-> Result<(), DynError> {
     let f = File::open(file)?;
     let reader = BufReader::new(f);
 
-    engine::print(expr)?;
+    regex::print(expr)?;
     println!();
 
+    // 正規表現のコンパイルは一度だけ行う
+    let re = Regex::new(expr)?;
     for line in reader.lines() {
         let line = line?;
-        for (i, _) in line.char_indices() {
-            if engine::do_matching(expr, &line[i..], i, true)? {
-                println!("hit!!!: {line}");
-                break;
+        if only_matching {
+            // マッチした部分を 1 つずつ表示
+            for m in matched_parts(&re, &line) {
+                println!("{m}");
             }
+        } else if re.is_match(&line) {
+            println!("hit!!!: {line}");
         }
     }
     Ok(())
 }
 
+// -o で表示する、行の中でマッチした部分を返す
+// 空文字列へのマッチは表示しない
+fn matched_parts<'t>(re: &Regex, line: &'t str) -> Vec<&'t str> {
+    re.find_iter(line).filter(|m| m.start() != m.end()).map(|m| m.as_str()).collect()
+}
+
+// clippy は Regex::new などを regex クレートのものとして検査するため、
+// エラーケースのテストや、評価器ごとにコンパイルし直すループで誤検知しないよう無効にする
 #[cfg(test)]
+#[allow(clippy::invalid_regex, clippy::regex_creation_in_loops)]
 mod tests {
-    use crate::engine::do_matching;
-    use crate::helpers::{safe_add, SafeAdd};
-
-    #[test]
-    fn test_safe_add() {
-        let u = 10;
-        assert_eq!(Some(30), u.safe_add(&20));
-
-        let u = !0; // 2^64 -1 (64bit cpuの場合) 18446744073709551615
-        assert_eq!(None, u.safe_add(&1));
+    use std::borrow::Cow;
+    use regex::{do_matching, Captures, Regex, RegexBuilder};
 
-        let u = 18446744073709551614; // ギリギリ足せる
-        assert_eq!(Some(18446744073709551615), u.safe_add(&1));
+    // (正規表現, 文字列, マッチした箇所の範囲)
+    type Case = (&'static str, &'static str, &'static [(usize, usize)]);
 
-        // エラーのクロージャ渡してもOK
-        let mut n = 10;
-        assert!(safe_add(&mut n, &20, || ()).is_ok());
+    // (正規表現, 文字列, 最左のマッチの範囲)
+    type Span = (&'static str, &'static str, Option<(usize, usize)>);
 
-        // エラーの確認
-        let mut n = !0;
-        assert!(safe_add(&mut n, &1, || ()).is_err());
+    // 評価器を指定してコンパイル
+    fn compile(expr: &str, is_depth: bool) -> Regex {
+        RegexBuilder::new(expr).depth_first(is_depth).build().unwrap()
     }
 
     #[test]
@@ -82,4 +90,1109 @@ mod tests {
         assert!(!do_matching("(ab|cd)+", "", 0, true).unwrap());
         assert!(!do_matching("abc?", "acb", 0, true).unwrap());
     }
+
+    #[test]
+    fn test_matching_width() {
+        // パースエラー
+        assert!(do_matching("+b", "bbb", 0, false).is_err());
+
+        // マッチ成功
+        assert!(do_matching("abc|def", "def", 0, false).unwrap());
+        assert!(do_matching("(abc)*", "abcabc", 0, false).unwrap());
+        assert!(do_matching("(ab|cd)+", "abcdcd", 0, false).unwrap());
+        assert!(do_matching("abc?", "ab", 0, false).unwrap());
+        assert!(do_matching("a.c", "abc", 0, false).unwrap());
+        assert!(do_matching("^ab", "abc", 0, false).unwrap());
+        assert!(do_matching("ab$", "ab", 0, false).unwrap());
+
+        // マッチしない
+        assert!(!do_matching("abc|def", "efa", 0, false).unwrap());
+        assert!(!do_matching("(ab|cd)+", "", 0, false).unwrap());
+        assert!(!do_matching("abc?", "acb", 0, false).unwrap());
+        assert!(!do_matching("a.", "a", 0, false).unwrap());
+        assert!(!do_matching("^ab", "ab", 1, false).unwrap());
+        assert!(!do_matching("ab$", "abc", 0, false).unwrap());
+
+        // a?^n a^n も線形時間で評価できる (深さ優先だと 2^n 通りの探索になる)
+        let n = 64;
+        let expr = format!("{}{}", "a?".repeat(n), "a".repeat(n));
+        assert!(do_matching(&expr, &"a".repeat(n), 0, false).unwrap());
+        assert!(!do_matching(&expr, &"a".repeat(n - 1), 0, false).unwrap());
+    }
+
+    #[test]
+    fn test_class() {
+        // パースエラー
+        assert!(do_matching("[abc", "a", 0, true).is_err());
+        assert!(do_matching("[]", "a", 0, true).is_err());
+        assert!(do_matching("[z-a]", "a", 0, true).is_err());
+        assert!(do_matching("[\\q]", "a", 0, true).is_err());
+
+        for is_depth in [true, false] {
+            // 範囲と単一文字
+            assert!(do_matching("[a-z0-9_]+", "ab_09", 0, is_depth).unwrap());
+            assert!(do_matching("x[a-c]y", "xby", 0, is_depth).unwrap());
+            assert!(!do_matching("x[a-c]y", "xdy", 0, is_depth).unwrap());
+            assert!(!do_matching("[a-z]", "", 0, is_depth).unwrap());
+
+            // 否定
+            assert!(do_matching("\"[^\"]*\"", "\"abc\"", 0, is_depth).unwrap());
+            assert!(!do_matching("[^\"]", "\"", 0, is_depth).unwrap());
+            assert!(do_matching("[^a-c]", "あ", 0, is_depth).unwrap());
+
+            // エスケープした ] と -、先頭・末尾の -
+            assert!(do_matching("[\\]]", "]", 0, is_depth).unwrap());
+            assert!(do_matching("[a\\-z]", "-", 0, is_depth).unwrap());
+            assert!(!do_matching("[a\\-z]", "b", 0, is_depth).unwrap());
+            assert!(do_matching("[-a]", "-", 0, is_depth).unwrap());
+            assert!(do_matching("[a-]", "-", 0, is_depth).unwrap());
+
+            // 文字クラス内の特殊文字はただの文字
+            assert!(do_matching("[.*]", "*", 0, is_depth).unwrap());
+            assert!(!do_matching("[.*]", "a", 0, is_depth).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_repeat() {
+        // パースエラー
+        assert!(do_matching("a{3,1}", "aaa", 0, true).is_err());
+        assert!(do_matching("a{3", "aaa", 0, true).is_err());
+        assert!(do_matching("a{3,", "aaa", 0, true).is_err());
+        assert!(do_matching("a{,3}", "aaa", 0, true).is_err());
+        assert!(do_matching("a{x}", "aaa", 0, true).is_err());
+        assert!(do_matching("{3}", "aaa", 0, true).is_err());
+
+        for is_depth in [true, false] {
+            // {n}
+            assert!(do_matching("^a{3}$", "aaa", 0, is_depth).unwrap());
+            assert!(!do_matching("^a{3}$", "aa", 0, is_depth).unwrap());
+            assert!(!do_matching("^a{3}$", "aaaa", 0, is_depth).unwrap());
+
+            // {n,}
+            assert!(do_matching("^a{2,}$", "aa", 0, is_depth).unwrap());
+            assert!(do_matching("^a{2,}$", "aaaaa", 0, is_depth).unwrap());
+            assert!(!do_matching("^a{2,}$", "a", 0, is_depth).unwrap());
+
+            // {n,m}
+            assert!(do_matching("^(ab){1,2}$", "ab", 0, is_depth).unwrap());
+            assert!(do_matching("^(ab){1,2}$", "abab", 0, is_depth).unwrap());
+            assert!(!do_matching("^(ab){1,2}$", "ababab", 0, is_depth).unwrap());
+            assert!(do_matching("^x{0}y$", "y", 0, is_depth).unwrap());
+            assert!(do_matching("^[0-9]{2,4}-[0-9]{0,2}$", "123-", 0, is_depth).unwrap());
+
+            // エスケープした波カッコはただの文字
+            assert!(do_matching("a\\{2\\}", "a{2}", 0, is_depth).unwrap());
+        }
+
+        // 命令数の上限
+        assert!(RegexBuilder::new("a{1000}{1000}").build().is_err());
+        assert!(RegexBuilder::new("a{10}").size_limit(10).build().is_err());
+        assert!(RegexBuilder::new("a{10}").size_limit(11).build().is_ok());
+
+        // 上限に近い命令数でも、幅優先探索がスタックを使い果たさない
+        let re = compile("(?:a?){40000}b", false);
+        assert!(re.is_match("ab"));
+        assert!(!re.is_match("ac"));
+
+        // 上限を超える繰り返しは、コードを生成する前にエラーとする
+        assert!(Regex::new("a{4000000000}").is_err());
+        assert!(Regex::new("a{0,4000000000}").is_err());
+        assert!(Regex::new("(?:ab){2000000000,}").is_err());
+
+        // 空の式は何回繰り返しても空
+        for expr in ["a{0}{4000000000}", "(?:){4000000000}", "(?:){0,4000000000}", "x(?:a{0}){4000000000,}y"] {
+            let re = Regex::new(expr).unwrap();
+            assert!(re.is_match("xy"), "expr = {expr}");
+        }
+    }
+
+    #[test]
+    fn test_captures() {
+        for is_depth in [true, false] {
+            let re = compile("([a-z]+)=([0-9]+)", is_depth);
+            assert_eq!(re.captures_len(), 3);
+
+            // 位置は元の文字列のバイト単位
+            let caps = re.captures("キー: key=42;").unwrap();
+            assert_eq!(caps.len(), 3);
+            assert_eq!(&caps[0], "key=42");
+            assert_eq!(&caps[1], "key");
+            assert_eq!(&caps[2], "42");
+            assert_eq!(caps.get(0).unwrap().range(), 8..14);
+            assert_eq!(caps.get(1).unwrap().start(), 8);
+            assert_eq!(caps.get(2).unwrap().end(), 14);
+            assert!(caps.get(3).is_none());
+            assert!(re.captures("key=").is_none());
+
+            // マッチに参加しなかったグループは None
+            let re = compile("(a)|(b)", is_depth);
+            let caps = re.captures("b").unwrap();
+            assert!(caps.get(1).is_none());
+            assert_eq!(&caps[2], "b");
+
+            // 繰り返されたグループは最後にマッチした箇所
+            let re = compile("(a|b)+", is_depth);
+            let caps = re.captures("xabab").unwrap();
+            assert_eq!(caps.get(0).unwrap().range(), 1..5);
+            assert_eq!(caps.get(1).unwrap().range(), 4..5);
+
+            // 入れ子のグループは開きカッコの順に番号を振る
+            let re = compile("((a)(b))()", is_depth);
+            let caps = re.captures("ab").unwrap();
+            let groups = caps.iter().map(|m| m.map(|m| m.as_str())).collect::<Vec<_>>();
+            assert_eq!(groups, vec![Some("ab"), Some("ab"), Some("a"), Some("b"), Some("")]);
+
+            // 0 回の繰り返しに含まれるグループも数える
+            let re = compile("a(b){0}", is_depth);
+            assert_eq!(re.captures_len(), 2);
+            assert!(re.captures("a").unwrap().get(1).is_none());
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        for is_depth in [true, false] {
+            // 最も左でマッチした箇所を返す
+            let re = compile("[0-9]+", is_depth);
+            let m = re.find("ab 123 45").unwrap();
+            assert_eq!(m.start(), 3);
+            assert_eq!(m.end(), 6);
+            assert_eq!(m.as_str(), "123");
+            assert!(re.find("abc").is_none());
+
+            // 位置はバイト単位
+            let m = re.find("値は 42 です").unwrap();
+            assert_eq!(m.range(), 7..9);
+            assert_eq!(m.as_str(), "42");
+
+            // 開始位置を指定
+            let m = re.find_at("ab 123 45", 4).unwrap();
+            assert_eq!(m.range(), 4..6);
+            let m = re.find_at("ab 123 45", 6).unwrap();
+            assert_eq!(m.range(), 7..9);
+            assert!(re.find_at("ab 123 45", 9).is_none());
+
+            // 開始位置より前の文字列も ^ の判定に使う
+            let re = compile("^a", is_depth);
+            assert!(re.find_at("aa", 1).is_none());
+            assert_eq!(re.find("aa").unwrap().range(), 0..1);
+
+            // 左側を優先し、同じ位置では優先度の高い選択肢を選ぶ
+            let re = compile("b|abc|ab", is_depth);
+            assert_eq!(re.find("xabc").unwrap().as_str(), "abc");
+            let re = compile("ab|abc", is_depth);
+            assert_eq!(re.find("xabc").unwrap().as_str(), "ab");
+
+            // 空文字列にマッチ
+            let re = compile("x*", is_depth);
+            assert_eq!(re.find("abc").unwrap().range(), 0..0);
+            assert_eq!(re.find_at("abc", 3).unwrap().range(), 3..3);
+
+            // キャプチャも開始位置を指定できる
+            let re = compile("([a-z])([0-9])", is_depth);
+            let caps = re.captures_at("a1 b2", 1).unwrap();
+            assert_eq!(&caps[0], "b2");
+            assert_eq!(caps.get(1).unwrap().range(), 3..4);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_find_at_not_char_boundary() {
+        Regex::new("a").unwrap().find_at("あa", 1);
+    }
+
+    #[test]
+    fn test_find_iter() {
+        let cases: &[Case] = &[
+            ("[0-9]+", "1, 22, 333", &[(0, 1), (3, 5), (7, 10)]),
+            ("[0-9]+", "abc", &[]),
+            ("ab|a", "aabab", &[(0, 1), (1, 3), (3, 5)]),
+            // 空文字列へのマッチ
+            ("a*", "baaa", &[(0, 0), (1, 4)]),
+            ("a*", "", &[(0, 0)]),
+            ("x*", "ab", &[(0, 0), (1, 1), (2, 2)]),
+            ("a*", "aab", &[(0, 2), (3, 3)]),
+            ("a?", "aba", &[(0, 1), (2, 3)]),
+            // マルチバイト文字は文字単位で進み、位置はバイト単位
+            ("x*", "あい", &[(0, 0), (3, 3), (6, 6)]),
+            ("い+", "あいい", &[(3, 9)]),
+            // ^ は文字列の先頭でのみマッチ
+            ("^a", "aaa", &[(0, 1)]),
+        ];
+
+        for is_depth in [true, false] {
+            for (expr, line, expected) in cases {
+                let re = compile(expr, is_depth);
+                let ranges = re
+                    .find_iter(line)
+                    .map(|m| (m.start(), m.end()))
+                    .collect::<Vec<_>>();
+                assert_eq!(&ranges, expected, "expr = {expr}, line = {line}, is_depth = {is_depth}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_matched_parts() {
+        // -o では空文字列へのマッチを表示しない
+        let parts = |expr, line| super::matched_parts(&Regex::new(expr).unwrap(), line);
+        assert_eq!(parts("a*", "baaacaa"), vec!["aaa", "aa"]);
+        assert_eq!(parts("a*", "bcd"), Vec::<&str>::new());
+        assert_eq!(parts("[0-9]+", "1, 22"), vec!["1", "22"]);
+    }
+
+    #[test]
+    fn test_replace() {
+        for is_depth in [true, false] {
+            let re = compile("([a-z]+)=([0-9]+)", is_depth);
+
+            // テンプレート
+            assert_eq!(re.replace("a=1 b=2", "$2=$1"), "1=a b=2");
+            assert_eq!(re.replace_all("a=1 b=2", "$2=$1"), "1=a 2=b");
+            assert_eq!(re.replacen("a=1 b=2 c=3", 2, "<$0>"), "<a=1> <b=2> c=3");
+            assert_eq!(re.replace_all("a=1", "${2}0"), "10");
+            assert_eq!(re.replace_all("a=1", "$20"), ""); // 20 番目のグループは存在しない
+            assert_eq!(re.replace_all("a=1", "$$1 costs $"), "$1 costs $");
+            assert_eq!(re.replace_all("a=1", "${1"), "${1");
+            assert_eq!(re.replace_all("a=1", "$name"), ""); // 存在しない名前
+            assert_eq!(re.replace_all("a=1", String::from("[$1]")), "[a]");
+
+            // クロージャ
+            let replaced = re.replace_all("a=1 b=2", |caps: &Captures| {
+                format!("{}={}", caps[1].to_uppercase(), &caps[2])
+            });
+            assert_eq!(replaced, "A=1 B=2");
+
+            // マッチしない場合はコピーしない
+            let replaced = re.replace_all("no match", "x");
+            assert!(matches!(replaced, Cow::Borrowed("no match")));
+            assert!(matches!(re.replace_all("a=1", "x"), Cow::Owned(_)));
+
+            // 空文字列へのマッチ
+            let re = compile("a*", is_depth);
+            assert_eq!(re.replace_all("baaac", "-"), "-b-c-");
+            let re = compile("x*", is_depth);
+            assert_eq!(re.replace_all("あい", "|"), "|あ|い|");
+
+            // マッチに参加しなかったグループは空文字列
+            let re = compile("(a)|(b)", is_depth);
+            assert_eq!(re.replace_all("ab", "[$1:$2]"), "[a:][:b]");
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        for is_depth in [true, false] {
+            let re = compile("[ ]*,[ ]*", is_depth);
+            let split = |line| re.split(line).collect::<Vec<_>>();
+            assert_eq!(split("a, b ,c"), vec!["a", "b", "c"]);
+            assert_eq!(split(",a,,b,"), vec!["", "a", "", "b", ""]);
+            assert_eq!(split("abc"), vec!["abc"]);
+            assert_eq!(split(""), vec![""]);
+
+            // 上限つき
+            let splitn = |line, limit| re.splitn(line, limit).collect::<Vec<_>>();
+            assert_eq!(splitn("a, b, c", 0), Vec::<&str>::new());
+            assert_eq!(splitn("a, b, c", 1), vec!["a, b, c"]);
+            assert_eq!(splitn("a, b, c", 2), vec!["a", "b, c"]);
+            assert_eq!(splitn("a, b, c", 3), vec!["a", "b", "c"]);
+            assert_eq!(splitn("a, b, c", 4), vec!["a", "b", "c"]);
+            assert_eq!(splitn("a,", 2), vec!["a", ""]);
+
+            // 空文字列にマッチする区切り
+            let re = compile("x*", is_depth);
+            assert_eq!(re.split("aあb").collect::<Vec<_>>(), vec!["", "a", "あ", "b", ""]);
+            let re = compile("[0-9]*", is_depth);
+            assert_eq!(re.split("a12b").collect::<Vec<_>>(), vec!["", "a", "b", ""]);
+        }
+    }
+
+    #[test]
+    fn test_anchor() {
+        let cases: &[Case] = &[
+            // 選択の中の ^ と $
+            ("(^a|b)", "ab", &[(0, 1), (1, 2)]),
+            ("(^a|b)", "aab", &[(0, 1), (2, 3)]),
+            ("a$|b", "aba", &[(1, 2), (2, 3)]),
+            ("a$|b", "ab", &[(1, 2)]),
+            ("x|^", "ax", &[(0, 0), (1, 2)]),
+            // グループの中の ^ と $
+            ("(a|^)b", "bab", &[(0, 1), (1, 3)]),
+            ("a(b$|c)", "acab", &[(0, 2), (2, 4)]),
+            ("(a$)*", "aa", &[(0, 0), (1, 2)]),
+            // $ のあとに命令が続く場合も評価を続ける
+            ("a$b", "ab", &[]),
+            ("a$$", "aa", &[(1, 2)]),
+            ("a$()", "a", &[(0, 1)]),
+            ("^^a", "aa", &[(0, 1)]),
+            // 空文字列
+            ("^$", "", &[(0, 0)]),
+            ("^$", "a", &[]),
+            ("$", "ab", &[(2, 2)]),
+        ];
+
+        for is_depth in [true, false] {
+            for (expr, line, expected) in cases {
+                let re = compile(expr, is_depth);
+                let ranges = re
+                    .find_iter(line)
+                    .map(|m| (m.start(), m.end()))
+                    .collect::<Vec<_>>();
+                assert_eq!(&ranges, expected, "expr = {expr}, line = {line}, is_depth = {is_depth}");
+            }
+
+            // 末尾の $ のあとのグループの位置
+            let caps = compile("(a)$(b?)", is_depth).captures("xa").unwrap();
+            assert_eq!(caps.get(1).unwrap().range(), 1..2);
+            assert_eq!(caps.get(2).unwrap().range(), 2..2);
+
+            assert!(do_matching("(^a|b)c", "bc", 0, is_depth).unwrap());
+            assert!(!do_matching("(^a|b)c", "ac", 1, is_depth).unwrap());
+            assert!(do_matching("a$|b", "a", 0, is_depth).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_multi_line() {
+        let line = "ab\ncd\n";
+        for is_depth in [true, false] {
+            // マルチラインモードでない場合は文字列の先頭と末尾のみ
+            let re = compile("^[a-z]+$", is_depth);
+            assert!(re.find(line).is_none());
+
+            // 各行の先頭と末尾
+            let re = RegexBuilder::new("^[a-z]+$").multi_line(true).depth_first(is_depth).build().unwrap();
+            let lines = re.find_iter(line).map(|m| m.as_str()).collect::<Vec<_>>();
+            assert_eq!(lines, vec!["ab", "cd"]);
+            let re = RegexBuilder::new("^").multi_line(true).depth_first(is_depth).build().unwrap();
+            let starts = re.find_iter(line).map(|m| m.start()).collect::<Vec<_>>();
+            assert_eq!(starts, vec![0, 3, 6]);
+            let re = RegexBuilder::new("$").multi_line(true).depth_first(is_depth).build().unwrap();
+            let ends = re.find_iter(line).map(|m| m.start()).collect::<Vec<_>>();
+            assert_eq!(ends, vec![2, 5, 6]);
+
+            // (?m) で有効にする
+            let re = compile("(?m)^c", is_depth);
+            assert_eq!(re.find(line).unwrap().range(), 3..4);
+            let re = compile("b(?m)$", is_depth);
+            assert_eq!(re.find(line).unwrap().range(), 1..2);
+
+            // (?m) はグループの残りの部分にのみ適用する
+            let re = compile("(x|(?m)^c)", is_depth);
+            assert!(re.is_match(line));
+            let re = compile("((?m)x|y)^c", is_depth);
+            assert!(!re.is_match(line));
+
+            // \A と \z はフラグによらず文字列の先頭と末尾
+            let re = compile("(?m)\\Ac", is_depth);
+            assert!(!re.is_match(line));
+            let re = compile("(?m)\\Aab", is_depth);
+            assert!(re.is_match(line));
+            let re = compile("(?m)b\\z", is_depth);
+            assert!(!re.is_match(line));
+            let re = compile("(?m)d\n\\z", is_depth);
+            assert!(re.is_match(line));
+        }
+
+        // 不明なフラグ
+        assert!(Regex::new("(?q)a").is_err());
+        assert!(Regex::new("(?m").is_err());
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        for is_depth in [true, false] {
+            // ビルダで有効にする
+            let re = RegexBuilder::new("error").case_insensitive(true).depth_first(is_depth).build().unwrap();
+            assert!(re.is_match("Error"));
+            assert!(re.is_match("ERROR"));
+            assert!(!re.is_match("err0r"));
+            assert!(!compile("error", is_depth).is_match("ERROR"));
+
+            // (?i) で有効にし、グループの外には影響しない
+            let re = compile("((?i)ab)c", is_depth);
+            assert!(re.is_match("ABc"));
+            assert!(!re.is_match("ABC"));
+
+            // 文字クラスと範囲
+            let re = compile("(?i)^[a-c]+$", is_depth);
+            assert!(re.is_match("aBcCA"));
+            assert!(!re.is_match("abd"));
+            let re = compile("(?i)^[^x]$", is_depth);
+            assert!(!re.is_match("x"));
+            assert!(!re.is_match("X"));
+            assert!(re.is_match("y"));
+
+            // Unicode の単純大文字小文字変換
+            let re = compile("(?i)σ", is_depth);
+            assert!(re.is_match("Σ"));
+            assert!(re.is_match("ς"));
+            let re = compile("(?i)k", is_depth);
+            assert!(re.is_match("\u{212A}")); // ケルビン記号
+            let re = compile("(?i)ſ", is_depth);
+            assert!(re.is_match("S"));
+            let re = compile("(?i)straße", is_depth);
+            assert!(re.is_match("STRAẞE"));
+            assert!(!re.is_match("STRASSE")); // 1文字から複数文字への変換は行わない
+
+            // 大文字・小文字のない文字
+            let re = compile("(?i)あ1_", is_depth);
+            assert!(re.is_match("あ1_"));
+        }
+    }
+
+    #[test]
+    fn test_perl_class() {
+        for is_depth in [true, false] {
+            let re = compile(r"^\d+-\w+\s\S$", is_depth);
+            assert!(re.is_match("2024-log_1 x"));
+            assert!(!re.is_match("2024-log_1  "));
+            let re = compile(r"^\D\W$", is_depth);
+            assert!(re.is_match("a-"));
+            assert!(!re.is_match("1-"));
+            assert!(!re.is_match("a_"));
+
+            // Unicode の定義（デフォルト）と ASCII のみの定義
+            let ascii = |expr| RegexBuilder::new(expr).unicode(false).depth_first(is_depth).build().unwrap();
+            assert!(compile(r"^\d$", is_depth).is_match("٣")); // アラビア・インド数字
+            assert!(!ascii(r"^\d$").is_match("٣"));
+            assert!(compile(r"^\w+$", is_depth).is_match("café"));
+            assert!(!ascii(r"^\w+$").is_match("café"));
+            assert!(ascii(r"^\W$").is_match("é"));
+            assert!(compile(r"^\s$", is_depth).is_match("\u{3000}")); // 全角スペース
+            assert!(!ascii(r"^\s$").is_match("\u{3000}"));
+            assert!(ascii(r"^\s+$").is_match(" \t\n\u{B}\u{C}\r"));
+
+            // 文字クラスの中
+            let re = compile(r"^[\d_]+$", is_depth);
+            assert!(re.is_match("1_2"));
+            assert!(!re.is_match("1a"));
+            let re = compile(r"^[^\s]+$", is_depth);
+            assert!(re.is_match("abc"));
+            assert!(!re.is_match("a c"));
+            let re = compile(r"^[\d-z]+$", is_depth); // \d の後ろの - はただの文字
+            assert!(re.is_match("1-z"));
+            assert!(!re.is_match("y"));
+            assert!(Regex::new(r"[a-\d]").is_err());
+            assert!(Regex::new(r"\q").is_err());
+
+            // 制御文字のエスケープ
+            let re = compile(r"a\tb\nc\rd\fe\vf\0", is_depth);
+            assert!(re.is_match("a\tb\nc\rd\u{C}e\u{B}f\0"));
+            let re = compile(r"^[\t\n]+$", is_depth);
+            assert!(re.is_match("\t\n"));
+        }
+    }
+
+    #[test]
+    fn test_hex_escape() {
+        for is_depth in [true, false] {
+            let re = compile(r"^\x41\x{3042}\u{1F600}\x{0}$", is_depth);
+            assert!(re.is_match("Aあ😀\0"));
+            let re = compile(r"\x1b\[\d+m", is_depth); // 制御文字
+            assert_eq!(re.find("ok \x1b[31mNG").map(|m| m.range()), Some(3..8));
+
+            // 文字クラスの中と範囲の端点
+            let re = compile(r"^[\x{1F600}-\x{1F64F}\x20]+$", is_depth);
+            assert!(re.is_match("😀 🙏"));
+            assert!(!re.is_match("😀!"));
+
+            // 大文字・小文字の同一視
+            let re = RegexBuilder::new(r"\x61").case_insensitive(true).depth_first(is_depth).build().unwrap();
+            assert!(re.is_match("A"));
+        }
+
+        // 不正な書式とコードポイント（位置は x や u の位置）
+        let err = |expr| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err(r"a\xZZ"), "ParseError: invalid hex escape: pos = 2");
+        assert_eq!(err(r"\x4"), "ParseError: invalid hex escape: pos = 1");
+        assert_eq!(err(r"\x{}"), "ParseError: invalid hex escape: pos = 1");
+        assert_eq!(err(r"\x{4g}"), "ParseError: invalid hex escape: pos = 1");
+        assert_eq!(err(r"\u0041"), "ParseError: invalid hex escape: pos = 1");
+        assert_eq!(err(r"\u{41"), "ParseError: no right brace: pos = 2");
+        assert_eq!(err(r"[\u{D800}]"), "ParseError: surrogate code point: pos = 2, code point = U+D800");
+        assert_eq!(err(r"\x{110000}"), "ParseError: code point out of range (max U+10FFFF): pos = 1");
+        assert_eq!(err(r"\x{FFFFFFFFFF}"), "ParseError: code point out of range (max U+10FFFF): pos = 1");
+    }
+
+    #[test]
+    fn test_unicode_property() {
+        for is_depth in [true, false] {
+            // General_Category
+            let re = compile(r"^\p{Lu}\p{Ll}+\pN$", is_depth);
+            assert!(re.is_match("Ωmega7"));
+            assert!(re.is_match("Éa٣"));
+            assert!(!re.is_match("omega7"));
+            let re = compile(r"^\p{L}+$", is_depth);
+            assert!(re.is_match("日本語abcΩ"));
+            assert!(!re.is_match("abc1"));
+
+            // Script と別名、名前の正規化
+            for expr in [r"^\p{Greek}+$", r"^\p{Grek}+$", r"^\p{sc=greek}+$", r"^\p{Script = Greek}+$"] {
+                let re = compile(expr, is_depth);
+                assert!(re.is_match("αβγΩ"));
+                assert!(!re.is_match("αβγa"));
+            }
+            assert!(compile(r"^\p{gc=Uppercase_Letter}$", is_depth).is_match("A"));
+            assert!(compile(r"^\p{uppercase letter}$", is_depth).is_match("A"));
+
+            // 否定
+            let re = compile(r"^\P{Han}+$", is_depth);
+            assert!(re.is_match("かなカナ"));
+            assert!(!re.is_match("漢字"));
+
+            // 文字クラスの中
+            let re = compile(r"^[\p{Hiragana}\p{Katakana}ー]+$", is_depth);
+            assert!(re.is_match("カタカナとひらがなー"));
+            assert!(!re.is_match("漢字"));
+            let re = compile(r"^[^\P{Greek}]+$", is_depth);
+            assert!(re.is_match("λ"));
+            assert!(!re.is_match("l"));
+
+            // 大文字・小文字の同一視
+            let re = RegexBuilder::new(r"^\p{Lu}+$").case_insensitive(true).depth_first(is_depth).build().unwrap();
+            assert!(re.is_match("aBc"));
+        }
+
+        let err = |expr| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err(r"a\p{Foo}"), "ParseError: unknown property: pos = 2, name = 'Foo'");
+        assert_eq!(err(r"\p{foo=Greek}"), "ParseError: unknown property: pos = 1, name = 'foo=Greek'");
+        assert_eq!(err(r"\p{Greek"), "ParseError: no right brace: pos = 2");
+        assert_eq!(err(r"\p"), "ParseError: unknown property: pos = 1, name = ''");
+    }
+
+    #[test]
+    fn test_lazy() {
+        for is_depth in [true, false] {
+            let find = |expr, line| compile(expr, is_depth).find(line).map(|m| m.as_str());
+            assert_eq!(find("<.+?>", "<a><bb>"), Some("<a>"));
+            assert_eq!(find("<[^x]+>", "<a><bb>"), Some("<a><bb>"));
+            assert_eq!(find("a*?", "aaa"), Some(""));
+            assert_eq!(find("a*?b", "aaab"), Some("aaab"));
+            assert_eq!(find("a+?", "aaa"), Some("a"));
+            assert_eq!(find("a??", "aaa"), Some(""));
+            assert_eq!(find("a??b", "ab"), Some("ab"));
+            assert_eq!(find("a{2,4}?", "aaaa"), Some("aa"));
+            assert_eq!(find("a{2,}?", "aaaa"), Some("aa"));
+            assert_eq!(find("a{2}?", "aaaa"), Some("aa"));
+
+            // 繰り返しの中の非貪欲な量指定子も、空文字列へのマッチを優先する
+            assert_eq!(find("(?:.??)*", "b"), Some(""));
+            assert_eq!(find("(?:a??)+", "aa"), Some(""));
+            assert_eq!(find("(?:a??){2,}", "aa"), Some(""));
+            assert_eq!(find("(?:a*?)*b", "aab"), Some("aab"));
+            assert_eq!(find("(?:a??b)*", "abab"), Some("abab"));
+            let re = compile("(?:(?:b)*?)*", is_depth);
+            assert_eq!(re.find_at("A b", 2).map(|m| m.range()), Some(2..2));
+
+            // キャプチャの位置も優先度に従う
+            let re = compile("(a+?)(a*)", is_depth);
+            let caps = re.captures("aaa").unwrap();
+            assert_eq!((&caps[1], &caps[2]), ("a", "aa"));
+
+            // 先頭の ? はエラーのまま
+            assert!(Regex::new("??").is_err());
+        }
+    }
+
+    #[test]
+    fn test_group() {
+        for is_depth in [true, false] {
+            // 非キャプチャグループは番号を消費しない
+            let re = compile("(?:ab)+(c)", is_depth);
+            assert_eq!(re.captures_len(), 2);
+            let caps = re.captures("xababc").unwrap();
+            assert_eq!(&caps[0], "ababc");
+            assert_eq!(&caps[1], "c");
+            let re = compile("^(?:a|bc)$", is_depth);
+            assert!(re.is_match("bc"));
+            assert!(!re.is_match("abc"));
+            assert!(compile("a(?:)b", is_depth).is_match("ab"));
+
+            // 名前付きグループも開きカッコの順に番号を振る
+            let re = compile(r"(?P<key>\w+)=(\d+)(?<unit>[a-z]*)", is_depth);
+            assert_eq!(re.captures_len(), 4);
+            let names = re.capture_names();
+            assert_eq!((names.get("key"), names.get("unit"), names.len()), (Some(&1), Some(&3), 2));
+            let caps = re.captures("size=42kb").unwrap();
+            assert_eq!(&caps["key"], "size");
+            assert_eq!(&caps[2], "42");
+            assert_eq!(caps.name("unit").unwrap().range(), 7..9);
+            assert!(caps.name("none").is_none());
+
+            // テンプレートでの参照
+            assert_eq!(re.replace("size=42kb", "${unit}:$key"), "kb:size");
+
+            // 入れ子
+            let re = compile("(?P<outer>a(?P<inner>b)?)c", is_depth);
+            let caps = re.captures("ac").unwrap();
+            assert_eq!(&caps["outer"], "a");
+            assert!(caps.name("inner").is_none());
+        }
+
+        let err = |expr| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err("a(?P<1a>b)"), "ParseError: invalid group name: pos = 1");
+        assert_eq!(err("(?P<>b)"), "ParseError: invalid group name: pos = 0");
+        assert_eq!(err("(?Pa)"), "ParseError: invalid group name: pos = 0");
+        assert_eq!(err("(?P<a-b>c)"), "ParseError: invalid group name: pos = 0");
+        assert_eq!(err("(?P<a"), "ParseError: invalid group name: pos = 0");
+        assert_eq!(err("(?P<a>x)(?<a>y)"), "ParseError: duplicate group name: pos = 8, name = 'a'");
+        assert!(Regex::new("(?:a").is_err());
+    }
+
+    #[test]
+    fn test_word_boundary() {
+        for is_depth in [true, false] {
+            let re = compile(r"\bfoo\b", is_depth);
+            let found = |re: &Regex, line| re.find_iter(line).map(|m| m.range()).collect::<Vec<_>>();
+            assert_eq!(found(&re, "foo foobar (foo) barfoo foo_ foo"), vec![0..3, 12..15, 29..32]);
+            assert!(!re.is_match("foo_bar"));
+
+            let re = compile(r"\Boo\B", is_depth);
+            assert_eq!(found(&re, "oo foo fooo"), vec![8..10]);
+
+            // 文字列の先頭と末尾、空文字列
+            assert!(compile(r"^\b", is_depth).is_match("a"));
+            assert!(!compile(r"\b", is_depth).is_match(""));
+            assert!(compile(r"^\B$", is_depth).is_match(""));
+            assert!(!compile(r"a\b\B", is_depth).is_match("a"));
+
+            // Unicode の単語の定義（デフォルト）と ASCII のみの定義
+            let re = compile(r"\bcafé\b", is_depth);
+            assert!(re.is_match("un café noir"));
+            assert!(!re.is_match("cafés"));
+            let re = RegexBuilder::new(r"\bcaf\b").unicode(false).depth_first(is_depth).build().unwrap();
+            assert!(re.is_match("café"));
+            assert!(!compile(r"\bcaf\b", is_depth).is_match("café"));
+            assert_eq!(found(&compile(r"\b\w+\b", is_depth), "日本語 と English"), vec![0..9, 10..13, 14..21]);
+        }
+
+        // 文字クラスの中では使えない
+        assert!(Regex::new(r"[\b]").is_err());
+    }
+
+    #[test]
+    fn test_look_around() {
+        let find = |expr, line| Regex::new(expr).unwrap().find(line).map(|m| m.range());
+
+        // 先読み
+        assert_eq!(find(r"\w+(?=:)", "key: value"), Some(0..3));
+        assert_eq!(find(r"foo(?!bar)", "foobar foobaz"), Some(7..10));
+        assert_eq!(find(r"(?=\d{3})\d+", "12 3456"), Some(3..7));
+        assert_eq!(find(r"a(?=b|c$)", "ad ac"), Some(3..4));
+
+        // 後読み
+        assert_eq!(find(r"(?<=\$)\d+", "cost: $42"), Some(7..9));
+        assert_eq!(find(r"(?<!\$)\b\d+", "$42 17"), Some(4..6));
+        assert_eq!(find(r"(?<=ab|c)d", "abd"), Some(2..3));
+        assert_eq!(find(r"(?<=ab|c)d", "cd"), Some(1..2));
+        assert_eq!(find(r"(?<=a{2,3})b", "ab aab"), Some(5..6));
+        assert_eq!(find(r"(?<=^a)b", "ab"), Some(1..2)); // 文字列の先頭も判定できる
+        assert_eq!(find(r"(?<=\b\w)x", "ax"), Some(1..2));
+        assert_eq!(find(r"(?<=a)", "a"), Some(1..1));
+        assert_eq!(find(r"(?<=aa)b", "ab"), None); // 先頭までの長さが足りない
+
+        // 入れ子
+        assert_eq!(find(r"(?<=a(?=b)).", "acab"), Some(3..4));
+
+        // 深さ優先探索は、長い文字列や空文字列にマッチする式の繰り返しでもスタックを使い果たさない
+        let long = "a".repeat(100_000);
+        assert_eq!(find(r"(?=a)a*", long.as_str()), Some(0..100_000));
+        assert_eq!(find(r"(?<=a)a+$", long.as_str()), Some(1..100_000));
+        assert_eq!(find(r"(a*)*$|(?=x)", "ab"), Some(2..2));
+        assert_eq!(find(r"(?:(?=a)|b)*c", "abc"), Some(1..3));
+        for expr in ["a*", "(a|b)*$", "(a*)*$", "(?:a*?)+$", "(a?)*$"] {
+            let re = compile(expr, true);
+            assert_eq!(re.find(&long).map(|m| m.range()), Some(0..100_000), "expr = {expr}");
+        }
+
+        // 肯定の先読み・後読みの中のキャプチャは残り、後続が失敗した場合は戻す
+        let re = Regex::new(r"(?=(\w+))\w*?(\d)|(?<=(x))y").unwrap();
+        let caps = re.captures("ab1").unwrap();
+        assert_eq!((&caps[1], &caps[2]), ("ab1", "1"));
+        let caps = re.captures("ab xy").unwrap();
+        assert!(caps.get(1).is_none());
+        assert_eq!(&caps[3], "x");
+
+        // 後読みの長さには上限が必要
+        let err = |expr| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err(r"a(?<=b+)"), "ParseError: look-behind must have a bounded length: pos = 1");
+        assert_eq!(err(r"(?<!a|b*)"), "ParseError: look-behind must have a bounded length: pos = 0");
+
+        // 幅優先探索では評価できない
+        let err = RegexBuilder::new("a(?=b)").depth_first(false).build().unwrap_err();
+        assert!(err.to_string().contains("not supported"));
+        assert!(do_matching("a(?=b)", "ab", 0, false).is_err());
+        assert!(do_matching("a(?=b)", "ab", 0, true).unwrap());
+    }
+
+    #[test]
+    fn test_backref() {
+        let find = |expr, line| Regex::new(expr).unwrap().find(line).map(|m| m.as_str());
+        assert_eq!(find(r"(\w+) \1", "hello world world!"), Some("world world"));
+        assert_eq!(find(r"(?P<q>['\x22]).*?\k<q>", r#"say "it's" ok"#), Some(r#""it's""#));
+        assert_eq!(find(r"^(a+)b\1$", "aabaa"), Some("aabaa"));
+        assert_eq!(find(r"^(a+)b\1$", "aaba"), None);
+        assert_eq!(find(r"(a)|b\1", "b"), None); // マッチに参加しなかったグループへの参照は失敗
+        assert_eq!(find(r"(a*)b\1c", "bc"), Some("bc")); // 空文字列への参照
+        assert_eq!(find(r"(?:(a)|b)\1", "aa"), Some("aa"));
+        // 繰り返しの中でグループ自身を参照する場合、グループの開始位置だけが更新された状態の参照は失敗
+        assert_eq!(find(r"(?:(a|b\1)c)+", "acbc"), Some("ac"));
+        assert_eq!(find(r"(?:(a|b\1)c)+", "acbac"), Some("ac"));
+        assert_eq!(find(r"(.)\1", "あいいう"), Some("いい"));
+        assert_eq!(find(r"(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)\10", "abcdefghijj"), Some("abcdefghijj"));
+        assert_eq!(find(r"(?=(\w))\1\1", "xyy"), Some("yy"));
+
+        // 大文字・小文字の同一視
+        let re = RegexBuilder::new(r"(\w+) \1").case_insensitive(true).build().unwrap();
+        assert_eq!(re.find("Straße STRAẞE").map(|m| m.as_str()), Some("Straße STRAẞE"));
+        assert!(Regex::new(r"(a) \1").unwrap().find("a A").is_none());
+
+        // is_match や find でもキャプチャの位置を使って評価する
+        let re = Regex::new(r"(\d)\1").unwrap();
+        assert!(re.is_match("1223"));
+        assert!(!re.is_match("1234"));
+        assert_eq!(re.find_iter("11 23 44").map(|m| m.range()).collect::<Vec<_>>(), vec![0..2, 6..8]);
+        assert_eq!(re.replace_all("x11y22", "<$1>"), "x<1>y<2>");
+        assert!(do_matching(r"(a)\1", "aa", 0, true).unwrap());
+
+        // 存在しないグループへの参照
+        let err = |expr| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err(r"(a)\2"), "ParseError: reference to undefined group: pos = 4, group = '2'");
+        assert_eq!(err(r"(a)\k<b>"), "ParseError: reference to undefined group: pos = 4, group = 'b'");
+        assert_eq!(err(r"\ka"), "ParseError: invalid escape: pos = 1, char = 'k'");
+        assert!(Regex::new(r"\1(a)").is_ok()); // 後ろで定義されるグループ
+        assert!(Regex::new(r"[\1]").is_err());
+
+        // 長い文字列でも評価できる
+        let long = "a".repeat(100_000);
+        assert_eq!(find(r"(a)\1*", long.as_str()).map(str::len), Some(100_000));
+        assert_eq!(find(r"^(a*)\1$", long.as_str()).map(str::len), Some(100_000));
+        assert_eq!(find(r"(a|b)(?:\1)*$", long.as_str()).map(str::len), Some(100_000));
+
+        // 幅優先探索では評価できない
+        let err = RegexBuilder::new(r"(a)\1").depth_first(false).build().unwrap_err();
+        assert_eq!(err.to_string(), "EvalError: not supported: backreferences require the depth-first evaluator");
+        assert!(do_matching(r"(a)\1", "aa", 0, false).is_err());
+    }
+
+    #[test]
+    fn test_atomic() {
+        let find = |expr, line| Regex::new(expr).unwrap().find(line).map(|m| m.as_str());
+
+        // アトミックグループは、一度マッチしたらバックトラックしない
+        assert_eq!(find("(?:a|ab)c", "abc"), Some("abc"));
+        assert_eq!(find("(?>a|ab)c", "abc"), None);
+        assert_eq!(find("(?>ab|a)c", "abc"), Some("abc"));
+        assert_eq!(find("x(?>a+)b", "xaab"), Some("xaab"));
+
+        // 強欲な量指定子
+        assert_eq!(find("a*+a", "aaa"), None);
+        assert_eq!(find("a++b", "aaab"), Some("aaab"));
+        assert_eq!(find("a?+a", "a"), None);
+        assert_eq!(find("a?+a", "aa"), Some("aa"));
+        assert_eq!(find("a{1,3}+a", "aaa"), None);
+        assert_eq!(find("a{1,3}+a", "aaaa"), Some("aaaa"));
+        assert_eq!(find(r#""[^"]*+""#, r#"say "hi" ok"#), Some(r#""hi""#));
+
+        // キャプチャはグループの中で記録した位置が残り、後続が失敗した場合は戻す
+        let re = Regex::new("(?>(a+))b|(a)c").unwrap();
+        let caps = re.captures("aab").unwrap();
+        assert_eq!(&caps[1], "aa");
+        let caps = re.captures("ac").unwrap();
+        assert!(caps.get(1).is_none());
+        assert_eq!(&caps[2], "a");
+
+        // 破滅的なバックトラックを防ぐ
+        let line = format!("{}c", "a".repeat(40));
+        let re = RegexBuilder::new("(?>a+)+b").depth_first(true).build().unwrap();
+        assert!(!re.is_match(&line));
+        let re = RegexBuilder::new("(a++)+b").depth_first(true).build().unwrap();
+        assert!(!re.is_match(&line));
+
+        // 空文字列にマッチするアトミックグループの繰り返しも終わる
+        assert_eq!(find("(?>a*)*b", "aab"), Some("aab"));
+        assert_eq!(find("(?>a*)*b", "b"), Some("b"));
+        assert_eq!(find("(?>a*)*b", "aac"), None);
+        assert_eq!(find("(?:a*+)*b", "aac"), None);
+        assert!(!Regex::new("(?>a*)*b").unwrap().is_match(&line));
+
+        // 幅優先探索では評価できない
+        let err = RegexBuilder::new("a*+").depth_first(false).build().unwrap_err();
+        assert!(err.to_string().contains("atomic groups and possessive quantifiers"));
+    }
+
+    #[test]
+    fn test_inline_flags() {
+        for is_depth in [true, false] {
+            // 複数のフラグと無効化
+            let re = compile("(?im)^abc$", is_depth);
+            assert!(re.is_match("x\nABC\ny"));
+            let ci = |expr| RegexBuilder::new(expr).case_insensitive(true).depth_first(is_depth).build().unwrap();
+            let re = ci("a(?-i)b");
+            assert!(re.is_match("Ab"));
+            assert!(!re.is_match("AB"));
+            let re = RegexBuilder::new("(?i-m)a$").multi_line(true).depth_first(is_depth).build().unwrap();
+            assert!(re.is_match("xA"));
+            assert!(!re.is_match("A\nx"));
+
+            // (?flags:...) はグループの中にのみ適用する
+            let re = compile("(?i:ab)c", is_depth);
+            assert!(re.is_match("ABc"));
+            assert!(!re.is_match("ABC"));
+            let re = ci("a(?-i:b)c");
+            assert!(re.is_match("AbC"));
+            assert!(!re.is_match("ABC"));
+            assert_eq!(compile("(?i:a)(b)", is_depth).captures_len(), 2); // キャプチャしない
+            let re = compile("(?i:a|b)c|d", is_depth);
+            assert!(re.is_match("Bc"));
+            assert!(!re.is_match("D"));
+            let re = compile("(?m:^a)|^b", is_depth);
+            assert!(re.is_match("x\na"));
+            assert!(!re.is_match("x\nb"));
+
+            // u フラグで \d などを ASCII のみにする
+            let re = compile(r"^(?-u:\d)\d$", is_depth);
+            assert!(re.is_match("1٣"));
+            assert!(!re.is_match("٣1"));
+        }
+
+        let err = |expr| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err("a(?)"), "ParseError: invalid flags: pos = 3");
+        assert_eq!(err("(?i-)"), "ParseError: invalid flags: pos = 4");
+        assert_eq!(err("(?-:a)"), "ParseError: invalid flags: pos = 3");
+        assert_eq!(err("(?i-m-s)"), "ParseError: invalid flags: pos = 5");
+        assert_eq!(err("(?iq)"), "ParseError: unknown flag: pos = 3, char = 'q'");
+        assert_eq!(err("(?i"), "ParseError: no right parenthesis");
+        assert_eq!(err("(?i:a"), "ParseError: no right parenthesis");
+        assert!(Regex::new("(?imsxu-imsxu)a").is_ok());
+    }
+
+    #[test]
+    fn test_extended() {
+        for is_depth in [true, false] {
+            let x = |expr| RegexBuilder::new(expr).extended(true).depth_first(is_depth).build().unwrap();
+            let re = x("
+                ^ (\\w+)      # キー
+                \\s* = \\s*   # 区切り
+                ([0-9] +) $   # 値
+            ");
+            let caps = re.captures("size = 42").unwrap();
+            assert_eq!((&caps[1], &caps[2]), ("size", "42"));
+            assert!(!re.is_match("size = 4 2"));
+
+            // エスケープした空白と #、文字クラスの中の空白と # は無視しない
+            let re = x(r"a\ b \# [ #]");
+            assert!(re.is_match("a b# "));
+            assert!(re.is_match("a b##"));
+            assert!(!re.is_match("ab##"));
+            assert!(!re.is_match("a b #"));
+
+            // (?x) とグループの中のみの (?x:...)
+            let re = compile("(?x) a b # c", is_depth);
+            assert!(re.is_match("ab"));
+            let re = compile("(?x: a b ) c d", is_depth);
+            assert!(re.is_match("ab c d"));
+            assert!(!re.is_match("abcd"));
+
+            // 量指定子との間の空白
+            let re = x("a + b");
+            assert_eq!(re.find("aaab").map(|m| m.as_str()), Some("aaab"));
+
+            // 量指定子と直後の ? や + の間の空白とコメントも無視する
+            let find = |expr, line| x(expr).find(line).map(|m| m.as_str());
+            assert_eq!(find("a + ?", "aaa"), Some("a")); // (a+)? ではなく a+?
+            assert_eq!(find("a{1,2} # コメント\n ?", "aaa"), Some("a"));
+            assert_eq!(find("a ? ?", "a"), Some(""));
+        }
+
+        // 強欲な量指定子の + も同様
+        let re = RegexBuilder::new("a * +a").extended(true).build().unwrap();
+        assert!(!re.is_match("aaa"));
+
+        // エラーの位置は元の文字列での位置
+        let err = |expr| RegexBuilder::new(expr).extended(true).build().unwrap_err().to_string();
+        assert_eq!(err("a # (\n  \\q"), "ParseError: invalid escape: pos = 9, char = 'q'");
+        assert_eq!(err("# コメント\n)"), "ParseError: invalid right parenthesis: pos = 7");
+        assert_eq!(err("   # a"), "ParseError: empty expression");
+    }
+
+    #[test]
+    fn test_dot_all() {
+        for is_depth in [true, false] {
+            // デフォルトでは . は改行にマッチしない
+            let re = compile("a.c", is_depth);
+            assert!(re.is_match("abc"));
+            assert!(re.is_match("a\rc"));
+            assert!(!re.is_match("a\nc"));
+
+            // (?s) とビルダーのドットオールモード
+            assert!(compile("(?s)a.c", is_depth).is_match("a\nc"));
+            assert!(compile("(?s:a.)c", is_depth).is_match("a\nc"));
+            assert!(!compile("(?s)(?-s:a.)c", is_depth).is_match("a\nc"));
+            let re = RegexBuilder::new("a.c").dot_all(true).depth_first(is_depth).build().unwrap();
+            assert!(re.is_match("a\nc"));
+
+            // \r\n を行の区切りとする場合は . が \r にもマッチしない
+            assert!(!compile("(?R)a.c", is_depth).is_match("a\rc"));
+            assert!(compile("(?Rs)a.c", is_depth).is_match("a\rc"));
+
+            // マルチラインモードの ^ と $ は \r\n の前後にマッチし、\r と \n の間にはマッチしない
+            let re = compile("(?mR)^b$", is_depth);
+            assert!(re.is_match("a\r\nb\r\nc"));
+            assert!(re.is_match("a\rb\rc"));
+            let re = compile("(?mR)$", is_depth);
+            assert_eq!(re.find_iter("a\r\n").map(|m| m.start()).collect::<Vec<_>>(), vec![1, 3]);
+            let re = compile("(?mR)^", is_depth);
+            assert_eq!(re.find_iter("a\r\n").map(|m| m.start()).collect::<Vec<_>>(), vec![0, 3]);
+
+            // \r\n を行の区切りとしない場合は \r は通常の文字
+            let re = compile("(?m)^b$", is_depth);
+            assert!(!re.is_match("a\r\nb\r\nc"));
+            assert!(re.is_match("a\nb\nc"));
+        }
+    }
+
+    #[test]
+    fn test_evaluators() {
+        // すべての評価器で同じ結果になることを確認する
+        let cases: &[Span] = &[
+            // . は文字列の末尾を越えない
+            ("a.", "a", None),
+            (".", "", None),
+            ("(?s)a.", "a", None),
+            ("(?s).", "", None),
+            ("(?s)a.", "ab", Some((0, 2))),
+            ("(?s).+", "ab\n", Some((0, 3))),
+            ("(?s).*", "", Some((0, 0))),
+            ("(?s).*b", "aab\nb", Some((0, 5))),
+            ("(?s).*?b", "aab\nb", Some((0, 3))),
+            ("(?s)a.{2}", "ab", None),
+            ("(?s)a.{2}", "abc", Some((0, 3))),
+            (".+", "ab\nc", Some((0, 2))),
+            (".*$", "ab", Some((0, 2))),
+            ("<.+>", "<a><b>", Some((0, 6))),
+            ("<.+?>", "<a><b>", Some((0, 3))),
+            ("x.", "ax", None),
+            // 優先順位と最左マッチ
+            ("a|ab", "ab", Some((0, 1))),
+            ("ab|a", "ab", Some((0, 2))),
+            ("a*", "baa", Some((0, 0))),
+            ("a+", "baa", Some((1, 3))),
+            ("a+?", "baa", Some((1, 2))),
+            ("(a|b)*c", "abac", Some((0, 4))),
+            ("a{2,3}", "aaaa", Some((0, 3))),
+            ("a{2,3}?", "aaaa", Some((0, 2))),
+            // 空文字列にマッチする式の繰り返し
+            ("(a*)*$", "ab", Some((2, 2))),
+            ("(a*)*b", "aab", Some((0, 3))),
+            ("(a*)+b", "aab", Some((0, 3))),
+            ("(a*?)*b", "aab", Some((0, 3))),
+            ("(?:a|)*b", "aab", Some((0, 3))),
+            ("(?:a{0}|a)*", "aa", Some((0, 0))), // 空の繰り返しのあとは、繰り返しを抜ける方が優先
+            ("(?:\\b|x)*", "x", Some((0, 0))),
+            ("(?:\\b|a)+", "a", Some((0, 0))),
+            ("(a|\\b)+", "a", Some((0, 1))),
+            ("(?:.??)*", "b", Some((0, 0))),
+            ("(?:a??)+b", "aab", Some((0, 3))),
+            ("(?:(?:b)*?)*a", "bba", Some((0, 3))),
+            ("(?:a{0}|a)*?b", "aab", Some((0, 3))),
+            ("(?:a*|b)*c", "abac", Some((0, 4))),
+            ("(?:^|a)*b", "aab", Some((0, 3))),
+            ("(?:$)*a", "ba", Some((1, 2))),
+            ("(a?){3}b", "ab", Some((0, 2))),
+            // アンカーと境界
+            ("^a|b$", "bab", Some((2, 3))),
+            ("(?m)^b", "a\nb", Some((2, 3))),
+            ("\\bb", "ab b", Some((3, 4))),
+            ("\\Bb", "b ab", Some((3, 4))),
+            // 文字クラスとエスケープ
+            ("[^a]+", "aabca", Some((2, 4))),
+            ("\\d+", "x12y", Some((1, 3))),
+            ("(?i)ab", "xAB", Some((1, 3))),
+            // 位置はバイト単位
+            (".", "あ", Some((0, 3))),
+            ("い.", "あいう", Some((3, 9))),
+            ("(?s)う.", "あいう", None),
+        ];
+
+        for (expr, line, expected) in cases {
+            // コンパイル済みの正規表現
+            for is_depth in [true, false] {
+                let re = compile(expr, is_depth);
+                let span = re.find(line).map(|m| (m.start(), m.end()));
+                assert_eq!(&span, expected, "expr = {expr}, line = {line}, is_depth = {is_depth}");
+            }
+
+            // do_matching は先頭からのマッチの有無のみを返す
+            for is_depth in [true, false] {
+                let is_match = do_matching(expr, line, 0, is_depth).unwrap();
+                let is_expected = matches!(expected, Some((0, _)));
+                assert_eq!(is_match, is_expected, "expr = {expr}, line = {line}, is_depth = {is_depth}");
+            }
+        }
+
+        // 空文字列にマッチする繰り返しのキャプチャ
+        // (正規表現, 文字列, 1 番目のグループの範囲)
+        let cases: &[Span] = &[
+            ("(?:(^))*", "", Some((0, 0))),
+            ("(a|)*", "aa", Some((1, 2))),
+            ("(a*)*$", "ab", Some((2, 2))),
+            ("(.*){1,}", "ab", Some((0, 2))),
+            ("(a*){2,}", "aa", Some((2, 2))),
+        ];
+        for (expr, line, expected) in cases {
+            for is_depth in [true, false] {
+                let caps = compile(expr, is_depth).captures(line).unwrap();
+                let span = caps.get(1).map(|m| (m.start(), m.end()));
+                assert_eq!(&span, expected, "expr = {expr}, line = {line}, is_depth = {is_depth}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_regex() {
+        // パースエラー
+        assert!(Regex::new("+b").is_err());
+        assert!(Regex::new("(ab").is_err());
+
+        let re = Regex::new("abc|(de|cd)+").unwrap();
+        assert_eq!(re.as_str(), "abc|(de|cd)+");
+
+        // 文字列のどこかにマッチすれば成功
+        assert!(re.is_match("abc"));
+        assert!(re.is_match("xxdecd"));
+        assert!(re.is_match("xxabcxx"));
+        assert!(!re.is_match("xxdx"));
+        assert!(!re.is_match(""));
+
+        // ^ は文字列の先頭でのみマッチ
+        let re = Regex::new("^ab").unwrap();
+        assert!(re.is_match("abc"));
+        assert!(!re.is_match("cab"));
+
+        // マルチバイト文字
+        let re = Regex::new("い.え").unwrap();
+        assert!(re.is_match("あいうえお"));
+    }
+
+    #[test]
+    fn test_regex_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Regex>();
+
+        // コンパイル済みの正規表現をスレッド間で共有
+        let re = std::sync::Arc::new(Regex::new("a(bc)+").unwrap());
+        let handles = (0..4)
+            .map(|_| {
+                let re = re.clone();
+                std::thread::spawn(move || re.is_match("xabcbc"))
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            assert!(h.join().unwrap());
+        }
+    }
 }
\ No newline at end of file